@@ -1,111 +1,287 @@
-use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+use std::{
+    f32::consts::{FRAC_PI_2, FRAC_PI_4},
+    ops::Range,
+    time::Instant,
+};
 
 use bevy::{
     prelude::*,
     render::{mesh::Indices, render_resource::PrimitiveTopology},
 };
-use bevy_mod_outline::ATTRIBUTE_OUTLINE_NORMAL;
+use bevy_mod_outline::{OutlineMeshExt, ATTRIBUTE_OUTLINE_NORMAL};
 use bevy_mod_picking::prelude::*;
-use geo::{Coord, CoordsIter, HaversineBearing, HaversineDistance, LineString, MapCoords, Winding};
+use geo::{Coord, MapCoords, MultiPolygon, Polygon, Winding};
 use itertools::Itertools;
 
-use super::{material::Materials, Building};
+use super::{material::Materials, parts::HasParts, roof::Roof, Building};
 use crate::{
-    common::{DecorateRequest, WorldPosition},
+    common::{MeshTask, WorldPosition},
+    decorate::{Decorate, DecorateQueue},
     overpass::Tags,
-    viewport::view_distance::ViewDistance,
+    projection::Projection,
+    viewport::{view_distance::ViewDistance, OriginCoordinate},
 };
 
 pub fn decorate_building(
-    query: Query<(Entity, &Building, &Tags, &WorldPosition), With<DecorateRequest>>,
+    query: Query<(Entity, &Building, &Tags, &WorldPosition), (With<Decorate>, Without<HasParts>)>,
     materials: Res<Materials>,
-    mut meshes: ResMut<Assets<Mesh>>,
+    origin: Res<OriginCoordinate>,
+    mut queue: ResMut<DecorateQueue>,
     mut commands: Commands,
 ) {
-    for (entity, building, tags, pos) in query.iter().take(100) {
-        commands.entity(entity).remove::<DecorateRequest>();
+    let start = Instant::now();
+    let mut count = 0;
+    let config = &crate::config::get().buildings;
 
-        let origin = pos.0;
+    for (entity, building, tags, pos) in query.iter() {
+        // the rest stays marked for the next frame
+        if count > 0 && !queue.has_time(start.elapsed()) {
+            break;
+        }
 
-        // translate coords into meters
-        let geometry = building.geometry.map_coords(|coord| {
-            let distance = origin.haversine_distance(&coord.into());
-            let bearing = origin.haversine_bearing(coord.into());
+        commands.entity(entity).remove::<Decorate>();
+        count += 1;
 
-            let ang = bearing.to_radians();
+        let min_height = tags.min_height().unwrap_or(0.);
+        let height = building.height(tags, config).max(min_height);
+        let roof = Roof::from_tags(tags);
 
-            Coord {
-                x: (distance * ang.sin()) as f32,
-                y: (distance * -ang.cos()) as f32,
-            }
+        let geometry = building.geometry.clone();
+        let projection = origin.projection();
+        let center = projection.project(pos.0);
+        let mesh = MeshTask::spawn(move || {
+            building_mesh(&geometry, projection, center, min_height..height, roof)
         });
 
-        let height = tags.building_height().unwrap_or(10.);
+        let kind = tags.get("building").or_else(|| tags.get("building:part"));
+        let material = match kind.map(|s| s.as_str()) {
+            Some(
+                "boathouse" | "bungalow" | "cabin" | "static_caravan" | "terrace" | "apartments"
+                | "house" | "residential" | "detached" | "semidetached_house",
+            ) => materials.residential.clone(),
+            Some("church" | "chapel" | "mosque" | "temple" | "religious") => {
+                materials.religious.clone()
+            }
+            Some("farm_auxiliary" | "barn" | "greenhouse") => materials.agricultural.clone(),
+            Some("school" | "university" | "kindergarten") => materials.school.clone(),
+            Some("manufacture" | "industrial") => materials.industrial.clone(),
+            Some("civic" | "public" | "stadium") => materials.civic.clone(),
+            Some("commercial") => materials.commercial.clone(),
+            Some("retail") => materials.retail.clone(),
+            Some("outbuilding") => materials.outbuilding.clone(),
+            Some("construction") => materials.construction.clone(),
+            Some("service" | "fire_station") => materials.service.clone(),
+            Some("farm") => materials.agricultural.clone(),
+            Some("warehouse") => materials.warehouse.clone(),
+            Some("office") => materials.office.clone(),
+            Some("hospital") => materials.hospital.clone(),
+            Some("hotel") => materials.hotel.clone(),
+            Some("train_station" | "transportation") => materials.transportation.clone(),
+            _ => materials.default.clone(),
+        };
+
+        let mut cmds = commands.entity(entity);
+        cmds.insert((mesh, material, PickableBundle::default()));
 
-        let exterior = geometry.exterior_coords_iter().collect::<LineString<f32>>();
-        let mut exterior = exterior.points_cw().map(Coord::from).collect::<Vec<_>>();
-        if exterior.first() == exterior.last() {
-            exterior.pop();
+        if let Some(name) = tags.name() {
+            cmds.insert(Name::new(name.to_string()));
         }
 
-        // 2d vertices for earcutr
-        let vertices = exterior.iter().flat_map(|c| [c.x, c.y]).collect::<Vec<_>>();
+        if height < 12. {
+            cmds.insert(ViewDistance(800.));
+        }
+    }
+
+    queue.record(start.elapsed(), count);
+}
+
+/// Extrudes the footprint of a building or building part over the given heights and puts the roof
+/// on top, around the projected `center`.
+fn building_mesh(
+    geometry: &MultiPolygon,
+    projection: Projection,
+    center: Coord,
+    heights: Range<f32>,
+    roof: Roof,
+) -> Option<Mesh> {
+    // translate coords into meters
+    let geometry = geometry.map_coords(|coord| projection.local(center, coord.into()));
+
+    let mut builder = MeshBuilder::default();
+
+    for polygon in &geometry {
+        let outline = rings(polygon);
 
         // apparently this can happen
-        if vertices.is_empty() {
-            error!("Building has no vertices");
+        if outline[0].len() < 3 {
             continue;
         }
 
-        // find the triangles
-        let indices = earcutr::earcut(&vertices, &[], 2);
-        let Ok(indices) = indices else {
-            error!("Failed to triangulate building: {:?}", indices);
-            continue;
+        let mut surface = roof.surface(polygon, heights.clone());
+        let faces = match surface.faces(polygon) {
+            Some(faces) => faces,
+            None => {
+                warn!("Failed to lay out roof, falling back to a flat one");
+                surface = roof.flat(heights.end);
+                surface.faces(polygon).unwrap_or_default()
+            }
         };
 
-        let mut indices = indices
-            .into_iter()
-            .map(|i| i as u32)
-            .array_chunks()
-            .flat_map(|[a, b, c]| [a, c, b])
-            .collect::<Vec<_>>();
+        for (plane, face) in faces {
+            for part in &face {
+                if let Err(e) = builder.add_cap(&rings(part), |c| surface.height(c), plane.normal())
+                {
+                    error!("Failed to triangulate building: {:?}", e);
+                }
+            }
+        }
 
-        // 3d vertices for the roof
-        let mut vertices = exterior
-            .iter()
-            .map(|c| [c.x, height, c.y])
-            .collect::<Vec<_>>();
+        // parts floating above the ground can be seen from below
+        if heights.start > 0. {
+            if let Err(e) = builder.add_cap(&outline, |_| heights.start, Vec3::NEG_Y) {
+                error!("Failed to triangulate building: {:?}", e);
+            }
+        }
+
+        for ring in &outline {
+            builder.add_walls(&surface.split_ring(ring), heights.start, |c| surface.height(c));
+        }
+
+        // the hand-made outline normals only fit flat roofs on the ground
+        builder.smooth_outline |= !surface.is_flat() || heights.start > 0.;
+    }
+
+    if builder.vertices.is_empty() {
+        error!("Building has no vertices");
+        return None;
+    }
+
+    Some(builder.build())
+}
+
+/// Rings of a polygon without the closing coordinate. The exterior is wound clockwise and the
+/// holes counter-clockwise, so walls generated from either one face away from the building.
+fn rings(polygon: &Polygon<f32>) -> Vec<Vec<Coord<f32>>> {
+    let open = |ring: &mut Vec<Coord<f32>>| {
+        if ring.first() == ring.last() {
+            ring.pop();
+        }
+    };
+
+    let mut exterior = polygon
+        .exterior()
+        .points_cw()
+        .map(Coord::from)
+        .collect::<Vec<_>>();
+    open(&mut exterior);
+
+    let holes = polygon.interiors().iter().map(|interior| {
+        let mut hole = interior.points_ccw().map(Coord::from).collect::<Vec<_>>();
+        open(&mut hole);
+        hole
+    });
+
+    std::iter::once(exterior)
+        .chain(holes.filter(|hole| hole.len() >= 3))
+        .collect()
+}
+
+/// Outline normals along the top edge of a ring, tilted 45 degrees away from the building so
+/// the outline covers both the roof and the walls.
+fn roof_outline_normals(ring: &[Coord<f32>]) -> Vec<[f32; 3]> {
+    let mut outline_normals = ring
+        .iter()
+        .map(|c| Vec3::new(c.x, 0., c.y))
+        .circular_tuple_windows()
+        .map(|(prev, this, next)| {
+            let prev_angle = (this - prev).normalize();
+            let next_angle = (next - this).normalize();
+            let angle = (prev_angle + next_angle).normalize();
 
-        let mut normals = exterior.iter().map(|_| [0., 1., 0.]).collect::<Vec<_>>();
-        let mut colors = exterior
+            Quat::from_axis_angle(angle, FRAC_PI_4)
+                .mul_vec3(Vec3::Y)
+                .to_array()
+        })
+        .collect::<Vec<_>>();
+    outline_normals.rotate_right(1);
+
+    outline_normals
+}
+
+#[derive(Default)]
+struct MeshBuilder {
+    vertices: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    colors: Vec<[f32; 4]>,
+    outline_normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+    /// Derive the outline normals from the faces of the mesh instead.
+    smooth_outline: bool,
+}
+
+impl MeshBuilder {
+    /// Adds a roof face, or a floor if `normal` points down, covering the exterior ring with the
+    /// remaining rings cut out as holes.
+    fn add_cap(
+        &mut self,
+        rings: &[Vec<Coord<f32>>],
+        height: impl Fn(Coord<f32>) -> f32,
+        normal: Vec3,
+    ) -> Result<(), earcutr::Error> {
+        // 2d vertices for earcutr, holes are given by the index of their first vertex
+        let vertices = rings
             .iter()
-            .map(|_| [0.5, 0.5, 0.5, 0.5])
+            .flatten()
+            .flat_map(|c| [c.x, c.y])
             .collect::<Vec<_>>();
-
-        let mut outline_normals = exterior
+        let holes = rings
             .iter()
-            .map(|c| Vec3::new(c.x, height, c.y))
-            .circular_tuple_windows()
-            .map(|(prev, this, next)| {
-                let prev_angle = (this - prev).normalize();
-                let next_angle = (next - this).normalize();
-                let angle = (prev_angle + next_angle).normalize();
-
-                Quat::from_axis_angle(angle, FRAC_PI_4)
-                    .mul_vec3(Vec3::Y)
-                    .to_array()
+            .scan(0, |start, ring| {
+                *start += ring.len();
+                Some(*start)
             })
+            .take(rings.len() - 1)
             .collect::<Vec<_>>();
-        outline_normals.rotate_right(1);
 
-        // wall time
+        // find the triangles
+        let indices = earcutr::earcut(&vertices, &holes, 2)?;
+
+        let base = self.vertices.len() as u32;
+        let up = normal.y >= 0.;
+        self.indices.extend(
+            indices
+                .into_iter()
+                .map(|i| base + i as u32)
+                .array_chunks()
+                .flat_map(|[a, b, c]| if up { [a, c, b] } else { [a, b, c] }),
+        );
+
+        // 3d vertices for the cap
+        for ring in rings {
+            self.vertices
+                .extend(ring.iter().map(|c| [c.x, height(*c), c.y]));
+            self.normals.extend(ring.iter().map(|_| normal.to_array()));
+            self.colors
+                .extend(ring.iter().map(|_| [0.5, 0.5, 0.5, 0.5]));
+            self.outline_normals.extend(roof_outline_normals(ring));
+        }
+
+        Ok(())
+    }
+
+    /// Adds the walls along one ring, from `bottom` up to the given height at each vertex.
+    fn add_walls(&mut self, ring: &[Coord<f32>], bottom: f32, height: impl Fn(Coord<f32>) -> f32) {
         // each wall needs its own set of vertices and normals
-        let base = vertices.len() as u32;
-        vertices.extend(exterior.iter().circular_tuple_windows().flat_map(|(a, b)| {
-            [[a.x, 0., a.y], [a.x, height, a.y], [b.x, 0., b.y], [b.x, height, b.y]]
-        }));
-        let mut wall_normals = exterior
+        let base = self.vertices.len() as u32;
+        self.vertices
+            .extend(ring.iter().circular_tuple_windows().flat_map(|(a, b)| {
+                [[a.x, bottom, a.y], [a.x, height(*a), a.y], [b.x, bottom, b.y], [
+                    b.x,
+                    height(*b),
+                    b.y,
+                ]]
+            }));
+        let mut wall_normals = ring
             .iter()
             .map(|v| Vec3::new(v.x, 0., v.y))
             .circular_tuple_windows()
@@ -138,9 +314,9 @@ pub fn decorate_building(
             })
             .collect_vec();
         wall_normals.rotate_right(4);
-        normals.extend_from_slice(&wall_normals);
-        outline_normals.extend_from_slice(
-            &outline_normals
+        self.normals.extend_from_slice(&wall_normals);
+        self.outline_normals.extend(
+            roof_outline_normals(ring)
                 .iter()
                 .circular_tuple_windows()
                 .flat_map(|(a, b)| {
@@ -150,15 +326,14 @@ pub fn decorate_building(
                         [b[0], -b[1], b[2]],
                         [b[0], b[1], b[2]],
                     ]
-                })
-                .collect_vec(),
+                }),
         );
         let wall_a = 0.3;
-        colors.extend((0..exterior.len() * 2).flat_map(|_| {
+        self.colors.extend((0..ring.len() * 2).flat_map(|_| {
             [[wall_a / 5., wall_a / 5., wall_a / 5., 1.], [wall_a, wall_a, wall_a, 1.]]
         }));
-        indices.extend(
-            (0..exterior.len())
+        self.indices.extend(
+            (0..ring.len())
                 .flat_map(|i| {
                     let i = i as u32;
                     [[base + i * 4, base + i * 4 + 1, base + i * 4 + 2], [
@@ -169,49 +344,22 @@ pub fn decorate_building(
                 })
                 .flat_map(|[a, b, c]| [a, c, b]),
         );
+    }
 
+    fn build(self) -> Mesh {
         let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
-        mesh.insert_attribute(ATTRIBUTE_OUTLINE_NORMAL, outline_normals);
-        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.vertices);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        mesh.insert_attribute(ATTRIBUTE_OUTLINE_NORMAL, self.outline_normals);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
 
-        let material = match tags.get("building").map(|s| s.as_str()) {
-            Some(
-                "boathouse" | "bungalow" | "cabin" | "static_caravan" | "terrace" | "apartments"
-                | "house" | "residential" | "detached" | "semidetached_house",
-            ) => materials.residential.clone(),
-            Some("church" | "chapel" | "mosque" | "temple" | "religious") => {
-                materials.religious.clone()
+        if self.smooth_outline {
+            if let Err(e) = mesh.generate_outline_normals() {
+                error!("Failed to generate outline normals: {:?}", e);
             }
-            Some("farm_auxiliary" | "barn" | "greenhouse") => materials.agricultural.clone(),
-            Some("school" | "university" | "kindergarten") => materials.school.clone(),
-            Some("manufacture" | "industrial") => materials.industrial.clone(),
-            Some("civic" | "public" | "stadium") => materials.civic.clone(),
-            Some("commercial") => materials.commercial.clone(),
-            Some("retail") => materials.retail.clone(),
-            Some("outbuilding") => materials.outbuilding.clone(),
-            Some("construction") => materials.construction.clone(),
-            Some("service" | "fire_station") => materials.service.clone(),
-            Some("farm") => materials.agricultural.clone(),
-            Some("warehouse") => materials.warehouse.clone(),
-            Some("office") => materials.office.clone(),
-            Some("hospital") => materials.hospital.clone(),
-            Some("hotel") => materials.hotel.clone(),
-            Some("train_station" | "transportation") => materials.transportation.clone(),
-            _ => materials.default.clone(),
-        };
-
-        let mut cmds = commands.entity(entity);
-        cmds.insert((meshes.add(mesh), material, PickableBundle::default()));
-
-        if let Some(name) = tags.name() {
-            cmds.insert(Name::new(name.to_string()));
         }
 
-        if height < 12. {
-            cmds.insert(ViewDistance(800.));
-        }
+        mesh
     }
 }
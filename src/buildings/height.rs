@@ -0,0 +1,124 @@
+use serde::Deserialize;
+
+/// Settings for drawing buildings, read from the `[buildings]` section of the config file.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct BuildingsConfig {
+    /// Estimates for buildings without any height or levels tags. The first matching rule is used.
+    pub heights: Vec<HeightRule>,
+    /// Height in metres of buildings no rule matches.
+    pub default_height: f32,
+}
+
+impl Default for BuildingsConfig {
+    fn default() -> Self {
+        // types are separated by spaces to keep the table short
+        let rule = |types: &str, min_area, max_area, height| HeightRule {
+            types: types.split_whitespace().map(str::to_string).collect(),
+            min_area,
+            max_area,
+            height,
+        };
+
+        Self {
+            heights: vec![
+                rule("garage garages shed hut carport kiosk roof toilets container", 0., None, 3.),
+                rule("house detached semidetached_house bungalow cabin farm", 0., None, 6.),
+                rule("terrace barn farm_auxiliary greenhouse", 0., None, 8.),
+                rule("apartments residential dormitory", 1000., None, 24.),
+                rule("apartments residential dormitory", 0., None, 12.),
+                rule("commercial office hotel hospital", 2000., None, 30.),
+                rule("commercial office hotel hospital", 0., None, 15.),
+                rule("retail school university civic public", 0., None, 10.),
+                rule("industrial warehouse manufacture hangar", 0., None, 9.),
+                rule("church cathedral chapel mosque temple", 0., None, 15.),
+                rule("", 0., Some(40.), 3.),
+                rule("", 5000., None, 15.),
+            ],
+            default_height: 10.,
+        }
+    }
+}
+
+impl BuildingsConfig {
+    /// Guesses the height of a building from its type, the value of its `building` tag, and the
+    /// area of its footprint in square metres.
+    pub fn estimate_height(&self, kind: Option<&str>, area: f64) -> f32 {
+        self.heights
+            .iter()
+            .find(|rule| rule.matches(kind, area))
+            .map_or(self.default_height, |rule| rule.height)
+    }
+}
+
+/// Height of buildings of some types within a range of footprint areas.
+#[derive(Debug, Deserialize)]
+pub struct HeightRule {
+    /// Values of the `building` tag the rule applies to, or every building if empty.
+    #[serde(default)]
+    pub types: Vec<String>,
+    /// Smallest footprint area in square metres the rule applies to.
+    #[serde(default)]
+    pub min_area: f64,
+    /// Footprint area in square metres from which on the rule doesn't apply anymore.
+    #[serde(default)]
+    pub max_area: Option<f64>,
+    /// Height in metres.
+    pub height: f32,
+}
+
+impl HeightRule {
+    fn matches(&self, kind: Option<&str>, area: f64) -> bool {
+        let kind_matches =
+            self.types.is_empty() || kind.is_some_and(|kind| self.types.iter().any(|t| t == kind));
+
+        kind_matches && area >= self.min_area && self.max_area.map_or(true, |max| area < max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(heights: Vec<HeightRule>) -> BuildingsConfig {
+        BuildingsConfig { heights, default_height: 10. }
+    }
+
+    fn rule(types: &[&str], min_area: f64, max_area: Option<f64>, height: f32) -> HeightRule {
+        HeightRule {
+            types: types.iter().map(|t| t.to_string()).collect(),
+            min_area,
+            max_area,
+            height,
+        }
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let config = config(vec![rule(&["house"], 0., None, 6.), rule(&["house"], 0., None, 9.)]);
+
+        assert_eq!(config.estimate_height(Some("house"), 100.), 6.);
+        assert_eq!(config.estimate_height(Some("shed"), 100.), 10.);
+    }
+
+    #[test]
+    fn area_bounds() {
+        let config = config(vec![rule(&["office"], 100., Some(2000.), 15.)]);
+
+        assert_eq!(config.estimate_height(Some("office"), 99.), 10.);
+        // the minimum is inclusive, the maximum isn't
+        assert_eq!(config.estimate_height(Some("office"), 100.), 15.);
+        assert_eq!(config.estimate_height(Some("office"), 1999.), 15.);
+        assert_eq!(config.estimate_height(Some("office"), 2000.), 10.);
+    }
+
+    #[test]
+    fn empty_types_match_any_type() {
+        let config = config(vec![rule(&[], 0., Some(40.), 3.)]);
+
+        assert_eq!(config.estimate_height(Some("house"), 20.), 3.);
+        assert_eq!(config.estimate_height(Some("yes"), 20.), 3.);
+        assert_eq!(config.estimate_height(None, 20.), 3.);
+        assert_eq!(config.estimate_height(None, 50.), 10.);
+    }
+}
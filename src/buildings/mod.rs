@@ -1,17 +1,22 @@
 mod decorate;
+mod height;
 mod material;
+mod parts;
+mod roof;
 
 use anyhow::Context;
 use bevy::prelude::*;
 use bevy_mod_outline::{OutlineBundle, OutlineVolume};
 use bevy_mod_picking::{focus::PickingInteraction, selection::PickSelection};
-use geo::{Centroid, MultiPolygon};
-use serde_json::json;
+use geo::{Centroid, ChamberlainDuquetteArea, MultiPolygon};
 
+pub use self::height::BuildingsConfig;
+use self::parts::HasParts;
 use crate::{
-    common::{DecorateRequest, WorldPosition},
-    loading::{LoadRequest, LoadType, LoadingPlugin},
-    overpass::Element,
+    common::{insert_meshes, DecorateRequest, WorldPosition},
+    decorate::DecorateSet,
+    loading::{fetch, LoadRequest, LoadType, LoadingPlugin},
+    overpass::{Element, OsmId, Selector, Tags},
 };
 
 #[derive(Default)]
@@ -21,7 +26,20 @@ impl Plugin for BuildingsPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(LoadingPlugin::<Building>::new())
             .add_systems(Startup, material::init_materials)
-            .add_systems(Update, (decorate::decorate_building, update_outline));
+            .add_systems(
+                Update,
+                (
+                    decorate::decorate_building.in_set(DecorateSet),
+                    insert_meshes::<(With<Building>, Without<HasParts>)>,
+                    update_outline,
+                    // after decorating and inserting meshes, so outlines that got either in the
+                    // same frame lose them again
+                    parts::hide_outlines
+                        .after(DecorateSet)
+                        .after(insert_meshes::<(With<Building>, Without<HasParts>)>),
+                    parts::restore_outlines,
+                ),
+            );
     }
 }
 
@@ -66,39 +84,77 @@ fn update_outline(
 #[derive(Component)]
 pub struct Building {
     pub geometry: MultiPolygon,
+    /// Tagged `building:part`, one of the volumes that together make up a building.
+    pub part: bool,
 }
 
-impl LoadType for Building {
-    type Bundle = impl Bundle;
+impl Building {
+    /// Height from the ground to the top of the walls, or the roof if it's tagged with its full
+    /// height, estimated from the type and size of the building if there's nothing to go by.
+    pub fn height(&self, tags: &Tags, config: &BuildingsConfig) -> f32 {
+        tags.building_height().unwrap_or_else(|| {
+            let kind = tags.get("building").or_else(|| tags.get("building:part"));
+            let area = self.geometry.chamberlain_duquette_unsigned_area();
 
-    async fn load(req: LoadRequest) -> anyhow::Result<Vec<Self::Bundle>> {
-        let template = include_str!("../../assets/queries/buildings.ovp");
+            config.estimate_height(kind.map(|s| s.as_str()), area)
+        })
+    }
+}
 
-        let query = handlebars::Handlebars::new()
-            .render_template(template, &json!({ "bbox": req.bbox() }))
-            .context("Failed to render query")?;
+impl LoadType for Building {
+    const QUERY: &'static str = include_str!("../../assets/queries/buildings.ovp");
+    const SELECTORS: &'static [Selector] = &[
+        Selector::way("building"),
+        Selector::relation("building"),
+        Selector::way("building:part"),
+        Selector::relation("building:part"),
+    ];
 
-        let res = crate::overpass::load(&query)
+    type Bundle = impl Bundle;
+
+    async fn load(req: LoadRequest) -> anyhow::Result<Vec<(OsmId, Self::Bundle)>> {
+        let res = fetch::<Self>(&req)
             .await
             .context("Failed to load buildings")?;
 
         Ok(res
             .elements
             .into_iter()
-            .flat_map(|elem| match elem {
-                Element::Way(way) => way.polygon().map(|poly| {
-                    (
-                        Self { geometry: poly.into() },
-                        way.tags,
-                        WorldPosition(way.bounds.unwrap().centroid()),
-                        DecorateRequest,
-                    )
-                }),
-                Element::Relation(_rel) => {
-                    // TODO: implement
-                    None
+            .flat_map(|elem| {
+                let id = elem.osm_id();
+                let part = elem.tags().contains_key("building:part");
+
+                match elem {
+                    Element::Way(way) => way.polygon().map(|poly| {
+                        (
+                            id,
+                            (
+                                Self { geometry: poly.into(), part },
+                                way.tags,
+                                WorldPosition(way.bounds.unwrap().centroid()),
+                                DecorateRequest,
+                            ),
+                        )
+                    }),
+                    Element::Relation(rel) => {
+                        let geometry = rel.polygon()?;
+                        let center = match rel.bounds {
+                            Some(bounds) => bounds.centroid(),
+                            None => geometry.centroid()?,
+                        };
+
+                        Some((
+                            id,
+                            (
+                                Self { geometry, part },
+                                rel.tags,
+                                WorldPosition(center),
+                                DecorateRequest,
+                            ),
+                        ))
+                    }
+                    Element::Node(_) => None,
                 }
-                Element::Node(_) => None,
             })
             .collect())
     }
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+use bevy_mod_picking::prelude::*;
+use geo::{BoundingRect, Contains, InteriorPoint, Intersects};
+use itertools::Itertools;
+
+use super::Building;
+use crate::{
+    common::{DecorateRequest, MeshTask},
+    decorate::Decorate,
+};
+
+/// Marks a building outline that has `building:part`s inside it. Following the Simple 3D
+/// Buildings rules, only the parts are drawn and the outline itself is left without a mesh.
+#[derive(Component)]
+pub struct HasParts;
+
+/// Takes the mesh away from outlines that have parts, once either of them has been loaded.
+pub(super) fn hide_outlines(
+    added: Query<(), Added<Building>>,
+    buildings: Query<(Entity, &Building), Without<HasParts>>,
+    mut commands: Commands,
+) {
+    if added.is_empty() {
+        return;
+    }
+
+    let parts = buildings
+        .iter()
+        .filter(|(_, building)| building.part)
+        .filter_map(|(entity, building)| {
+            Some((added.contains(entity), building.geometry.interior_point()?))
+        })
+        .collect_vec();
+
+    if parts.is_empty() {
+        return;
+    }
+
+    let any_new_part = parts.iter().any(|(new, _)| *new);
+
+    for (entity, building) in buildings.iter().filter(|(_, building)| !building.part) {
+        // only pairs where at least one side is new need checking
+        let new = added.contains(entity);
+        if !new && !any_new_part {
+            continue;
+        }
+
+        let Some(bounds) = building.geometry.bounding_rect() else {
+            continue;
+        };
+
+        let has_parts = parts.iter().any(|(part_new, point)| {
+            (new || *part_new) && bounds.intersects(point) && building.geometry.contains(point)
+        });
+
+        if has_parts {
+            commands
+                .entity(entity)
+                .remove::<(DecorateRequest, Decorate, MeshTask, Handle<Mesh>, PickableBundle)>()
+                .insert(HasParts);
+        }
+    }
+}
+
+/// Decorates outlines again once all parts inside them have been unloaded.
+pub(super) fn restore_outlines(
+    mut removed: RemovedComponents<Building>,
+    outlines: Query<(Entity, &Building), With<HasParts>>,
+    buildings: Query<&Building, Without<HasParts>>,
+    mut commands: Commands,
+) {
+    if removed.read().count() == 0 || outlines.is_empty() {
+        return;
+    }
+
+    let parts = buildings
+        .iter()
+        .filter(|building| building.part)
+        .filter_map(|building| building.geometry.interior_point())
+        .collect_vec();
+
+    for (entity, outline) in &outlines {
+        let Some(bounds) = outline.geometry.bounding_rect() else {
+            continue;
+        };
+
+        let has_parts = parts
+            .iter()
+            .any(|point| bounds.intersects(point) && outline.geometry.contains(point));
+
+        if !has_parts {
+            commands
+                .entity(entity)
+                .remove::<HasParts>()
+                .insert(DecorateRequest);
+        }
+    }
+}
@@ -0,0 +1,607 @@
+use std::{
+    f32::consts::PI,
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+};
+
+use bevy::prelude::*;
+use geo::{BooleanOps, BoundingRect, Coord, LineString, MinimumRotatedRect, MultiPolygon, Polygon};
+
+use crate::overpass::Tags;
+
+/// Roof of a building as described by the `roof:*` tags of the Simple 3D Buildings scheme.
+#[derive(Clone, Copy, Debug)]
+pub struct Roof {
+    pub shape: RoofShape,
+    /// Height from the top of the walls to the top of the roof, if tagged.
+    pub height: Option<f32>,
+    /// Compass bearing in radians the main slope faces downhill.
+    pub direction: Option<f32>,
+    /// The ridge runs across the longer side of the building instead of along it.
+    pub across: bool,
+    /// Whether the building height includes the roof, as with `height`, or only counts the walls,
+    /// as with `building:levels`.
+    pub included: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoofShape {
+    Flat,
+    Skillion,
+    Gabled,
+    Hipped,
+    Pyramidal,
+    Dome,
+}
+
+impl Roof {
+    pub fn from_tags(tags: &Tags) -> Self {
+        let shape = match tags.get("roof:shape").map(|s| s.as_str()) {
+            Some("skillion") => RoofShape::Skillion,
+            Some("gabled") => RoofShape::Gabled,
+            Some("hipped" | "half-hipped" | "side_hipped") => RoofShape::Hipped,
+            Some("pyramidal") => RoofShape::Pyramidal,
+            Some("dome" | "onion") => RoofShape::Dome,
+            _ => RoofShape::Flat,
+        };
+
+        Self {
+            shape,
+            height: tags.roof_height(),
+            direction: tags.get("roof:direction").and_then(|d| parse_direction(d)),
+            across: tags.get("roof:orientation").is_some_and(|o| o == "across"),
+            included: tags.height().is_some(),
+        }
+    }
+
+    /// Lays the roof out over one polygon of the footprint of a building spanning `heights`, with
+    /// everything in local metres.
+    pub fn surface(&self, polygon: &Polygon<f32>, heights: Range<f32>) -> RoofSurface {
+        let height = heights.end;
+
+        if self.shape == RoofShape::Flat {
+            return self.flat(height);
+        }
+
+        let Some(frame) = Frame::new(polygon, self.direction, self.across) else {
+            return self.flat(height);
+        };
+
+        let run = match self.shape {
+            RoofShape::Skillion => 2. * frame.width,
+            RoofShape::Dome => frame.width.min(frame.length),
+            _ => frame.width,
+        };
+
+        // unless tagged, pitched roofs default to a 30 degree slope and domes to a half sphere
+        let mut roof_height = self.height.unwrap_or(match self.shape {
+            RoofShape::Dome => run,
+            _ => run * (PI / 6.).tan(),
+        });
+
+        let walls = if self.included {
+            roof_height = roof_height.min(height - heights.start);
+            height - roof_height
+        } else {
+            height
+        };
+
+        if roof_height <= 0. {
+            return RoofSurface::flat(walls);
+        }
+
+        RoofSurface {
+            walls,
+            planes: frame.planes(self.shape, roof_height),
+        }
+    }
+
+    /// A flat roof on a building of the given height. The walls go up to the top of the roof,
+    /// which adds its tagged height unless the building height already includes it.
+    pub fn flat(&self, height: f32) -> RoofSurface {
+        match self.height {
+            Some(roof_height) if !self.included => RoofSurface::flat(height + roof_height),
+            _ => RoofSurface::flat(height),
+        }
+    }
+}
+
+/// Parses a bearing in degrees or a compass direction like `NNE` into radians.
+fn parse_direction(s: &str) -> Option<f32> {
+    const POINTS: [&str; 16] = [
+        "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW",
+        "NW", "NNW",
+    ];
+
+    let degrees = match POINTS.iter().position(|p| p.eq_ignore_ascii_case(s.trim())) {
+        Some(i) => i as f32 * 22.5,
+        None => s.trim().parse().ok()?,
+    };
+
+    Some(f32::to_radians(degrees))
+}
+
+/// Axes a roof is laid out along: the ridge runs along `u`, the slopes face along `v`.
+struct Frame {
+    center: Vec2,
+    u: Vec2,
+    v: Vec2,
+    /// Half the extent of the footprint along `u`.
+    length: f32,
+    /// Half the extent of the footprint along `v`.
+    width: f32,
+}
+
+impl Frame {
+    fn new(polygon: &Polygon<f32>, direction: Option<f32>, across: bool) -> Option<Self> {
+        let v = match direction {
+            // bearings are clockwise from north, and north is towards negative y locally
+            Some(bearing) => Vec2::new(bearing.sin(), -bearing.cos()),
+            None => {
+                // the ridge follows the longer side of the smallest enclosing rectangle
+                let rect = polygon.minimum_rotated_rect()?;
+                let corners = rect.exterior().0.iter().map(|c| Vec2::new(c.x, c.y));
+                let [a, b, c] = corners.take(3).collect::<Vec<_>>().try_into().ok()?;
+
+                let (long, short) = if a.distance(b) >= b.distance(c) {
+                    (b - a, c - b)
+                } else {
+                    (c - b, b - a)
+                };
+
+                let axis = if across { long } else { short };
+                axis.try_normalize()?
+            }
+        };
+        let u = v.perp();
+
+        let extent = |axis: Vec2| {
+            polygon
+                .exterior()
+                .0
+                .iter()
+                .map(|c| axis.dot(Vec2::new(c.x, c.y)))
+                .fold((f32::MAX, f32::MIN), |(min, max), d| (min.min(d), max.max(d)))
+        };
+        let (u_min, u_max) = extent(u);
+        let (v_min, v_max) = extent(v);
+
+        let length = (u_max - u_min) / 2.;
+        let width = (v_max - v_min) / 2.;
+        if length <= 0. || width <= 0. {
+            return None;
+        }
+
+        Some(Self {
+            center: u * (u_min + length) + v * (v_min + width),
+            u,
+            v,
+            length,
+            width,
+        })
+    }
+
+    /// Plane rising towards the center with the given slope, reaching `height` at `distance` from
+    /// it along `axis`.
+    fn slope(&self, axis: Vec2, slope: f32, distance: f32, height: f32) -> Plane {
+        let gradient = -axis * slope;
+
+        Plane {
+            gradient,
+            offset: height + slope * distance - gradient.dot(self.center),
+        }
+    }
+
+    fn planes(&self, shape: RoofShape, height: f32) -> Vec<Plane> {
+        let side = height / self.width;
+
+        match shape {
+            RoofShape::Flat => vec![Plane::flat(0.)],
+            RoofShape::Skillion => vec![self.slope(self.v, side / 2., self.width, 0.)],
+            RoofShape::Gabled => {
+                vec![self.slope(self.v, side, 0., height), self.slope(-self.v, side, 0., height)]
+            }
+            RoofShape::Hipped => {
+                let ridge = (self.length - self.width).max(0.);
+
+                vec![
+                    self.slope(self.v, side, 0., height),
+                    self.slope(-self.v, side, 0., height),
+                    self.slope(self.u, side, ridge, height),
+                    self.slope(-self.u, side, ridge, height),
+                ]
+            }
+            RoofShape::Pyramidal => {
+                let end = height / self.length;
+
+                vec![
+                    self.slope(self.v, side, 0., height),
+                    self.slope(-self.v, side, 0., height),
+                    self.slope(self.u, end, 0., height),
+                    self.slope(-self.u, end, 0., height),
+                ]
+            }
+            RoofShape::Dome => {
+                // faceted by planes touching an ellipsoid, whose lowest ring stops just above the
+                // walls, and cut off at the walls in corners outside of it
+                let mut planes = vec![Plane::flat(height)];
+
+                for ring in 1..5 {
+                    let (sin, cos) = (ring as f32 * 16.5).to_radians().sin_cos();
+
+                    for step in 0..12 {
+                        let (y, x) = (step as f32 * PI / 6.).sin_cos();
+                        let gradient =
+                            -(self.u * x / self.length + self.v * y / self.width) * height * sin
+                                / cos;
+
+                        planes.push(Plane {
+                            gradient,
+                            offset: height / cos - gradient.dot(self.center),
+                        });
+                    }
+                }
+
+                planes
+            }
+        }
+    }
+}
+
+/// Height of a roof face above the top of the walls.
+#[derive(Clone, Copy, Debug)]
+pub struct Plane {
+    gradient: Vec2,
+    offset: f32,
+}
+
+impl Plane {
+    fn flat(height: f32) -> Self {
+        Self { gradient: Vec2::ZERO, offset: height }
+    }
+
+    fn at(&self, c: Coord<f32>) -> f32 {
+        self.gradient.dot(Vec2::new(c.x, c.y)) + self.offset
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        Vec3::new(-self.gradient.x, 1., -self.gradient.y).normalize()
+    }
+}
+
+/// A roof laid out over a polygon. Its height at any point is the lowest of its planes, so each
+/// plane forms one face, and the creases are where two of them meet.
+pub struct RoofSurface {
+    /// Height of the walls the roof sits on.
+    pub walls: f32,
+    planes: Vec<Plane>,
+}
+
+impl RoofSurface {
+    /// A flat roof on walls of the given height.
+    pub fn flat(walls: f32) -> Self {
+        Self { walls, planes: vec![Plane::flat(0.)] }
+    }
+
+    pub fn is_flat(&self) -> bool {
+        self.planes.len() == 1 && self.planes[0].gradient == Vec2::ZERO
+    }
+
+    /// Height of the roof above the walls at a point, before cutting it off at the walls.
+    fn roof(&self, c: Coord<f32>) -> f32 {
+        self.planes.iter().map(|p| p.at(c)).fold(f32::MAX, f32::min)
+    }
+
+    /// Height of the roof at a point.
+    pub fn height(&self, c: Coord<f32>) -> f32 {
+        self.walls + self.roof(c).max(0.)
+    }
+
+    /// Adds a vertex wherever an edge of the ring crosses a crease, or where the roof meets the
+    /// walls, so walls reach up to the roof along their whole length.
+    pub fn split_ring(&self, ring: &[Coord<f32>]) -> Vec<Coord<f32>> {
+        if self.planes.len() == 1 {
+            return ring.to_vec();
+        }
+
+        let mut split = Vec::with_capacity(ring.len());
+
+        for (i, &a) in ring.iter().enumerate() {
+            let b = ring[(i + 1) % ring.len()];
+            let edge = b - a;
+            let along = |plane: &Plane| plane.gradient.dot(Vec2::new(edge.x, edge.y));
+            let point = |t: f32| a + edge * t;
+
+            split.push(a);
+
+            // the lowest plane just past the start of the edge
+            let Some(mut active) = (0..self.planes.len()).min_by(|&i, &j| {
+                let c = point(1e-3);
+                self.planes[i].at(c).total_cmp(&self.planes[j].at(c))
+            }) else {
+                continue;
+            };
+
+            let mut t = 0.;
+            loop {
+                let plane = self.planes[active];
+
+                // the next plane along the edge to drop below the current one
+                let next = self
+                    .planes
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(j, other)| {
+                        let drop = along(other) - along(&plane);
+                        if drop >= 0. {
+                            return None;
+                        }
+
+                        let crossing = (plane.at(a) - other.at(a)) / drop;
+                        (crossing > t + 1e-4 && crossing < 1. - 1e-4).then_some((j, crossing))
+                    })
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+                let end = next.map_or(1., |(_, crossing)| crossing);
+                let ground = -plane.at(a) / along(&plane);
+                if ground > t + 1e-4 && ground < end - 1e-4 {
+                    split.push(point(ground));
+                }
+
+                let Some((j, crossing)) = next else {
+                    break;
+                };
+
+                split.push(point(crossing));
+                active = j;
+                t = crossing;
+            }
+        }
+
+        split
+    }
+
+    /// Cuts the polygon into the faces of the roof, with the plane each one lies in. Returns `None`
+    /// if that failed, as the boolean operations in `geo` can panic on nearly degenerate input.
+    pub fn faces(&self, polygon: &Polygon<f32>) -> Option<Vec<(Plane, MultiPolygon<f32>)>> {
+        if self.planes.len() == 1 {
+            return Some(vec![(self.planes[0], polygon.clone().into())]);
+        }
+
+        panic::catch_unwind(AssertUnwindSafe(|| self.cut(polygon))).ok()
+    }
+
+    fn cut(&self, polygon: &Polygon<f32>) -> Vec<(Plane, MultiPolygon<f32>)> {
+        let Some(bounds) = polygon.bounding_rect() else {
+            return Vec::new();
+        };
+        let margin = Coord { x: 1., y: 1. };
+        let (min, max) = (bounds.min() - margin, bounds.max() + margin);
+        let bounds = vec![min, Coord { x: max.x, y: min.y }, max, Coord { x: min.x, y: max.y }];
+
+        // parts of the footprint the roof doesn't cover, like the corners under a dome
+        let uncovered = polygon.exterior().0.iter().any(|c| self.roof(*c) < -1e-3);
+        let above = |region: Vec<Coord<f32>>, plane: &Plane| clip(&region, |c| -plane.at(c));
+
+        let mut faces = self
+            .planes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, plane)| {
+                let mut region = bounds.clone();
+
+                // keep where this plane is the lowest, leaving ties to the first of them
+                for (j, other) in self.planes.iter().enumerate().filter(|(j, _)| *j != i) {
+                    let gradient = plane.gradient - other.gradient;
+                    let offset = plane.offset - other.offset;
+
+                    if gradient.length_squared() < 1e-12 {
+                        if offset > 0. || (offset == 0. && j < i) {
+                            return None;
+                        }
+                        continue;
+                    }
+
+                    region = clip(&region, |c| gradient.dot(Vec2::new(c.x, c.y)) + offset);
+                    if region.len() < 3 {
+                        return None;
+                    }
+                }
+
+                if uncovered {
+                    region = above(region, plane);
+                    if region.len() < 3 {
+                        return None;
+                    }
+                }
+
+                let region = Polygon::new(LineString::from(region), vec![]);
+                let face = polygon.intersection(&region);
+
+                (!face.0.is_empty()).then_some((*plane, face))
+            })
+            .collect::<Vec<_>>();
+
+        if uncovered {
+            let covered = self.planes.iter().fold(bounds, above);
+            let rest = if covered.len() < 3 {
+                polygon.clone().into()
+            } else {
+                polygon.difference(&Polygon::new(LineString::from(covered), vec![]))
+            };
+
+            if !rest.0.is_empty() {
+                faces.push((Plane::flat(0.), rest));
+            }
+        }
+
+        faces
+    }
+}
+
+/// Clips a convex polygon to the half-plane where `side` isn't positive.
+fn clip(polygon: &[Coord<f32>], side: impl Fn(Coord<f32>) -> f32) -> Vec<Coord<f32>> {
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+
+    for (i, &a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        let (side_a, side_b) = (side(a), side(b));
+
+        if side_a <= 0. {
+            clipped.push(a);
+        }
+
+        if (side_a < 0. && side_b > 0.) || (side_a > 0. && side_b < 0.) {
+            clipped.push(a + (b - a) * (side_a / (side_a - side_b)));
+        }
+    }
+
+    clipped
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::Area;
+
+    use super::*;
+
+    /// 20 by 10 metres, with the long side along x.
+    fn rectangle() -> Polygon<f32> {
+        let ring = vec![(-10., -5.), (10., -5.), (10., 5.), (-10., 5.)];
+        Polygon::new(LineString::from(ring), vec![])
+    }
+
+    fn surface(shape: RoofShape) -> RoofSurface {
+        let roof = Roof {
+            shape,
+            height: Some(3.),
+            direction: None,
+            across: false,
+            included: false,
+        };
+
+        roof.surface(&rectangle(), 0.0..10.)
+    }
+
+    fn tags(pairs: &[(&str, &str)]) -> Tags {
+        Tags(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    fn height(surface: &RoofSurface, x: f32, y: f32) -> f32 {
+        surface.height(Coord { x, y })
+    }
+
+    /// Checks that the faces cover the footprint, and that the top of every wall follows the roof
+    /// edge above it, which only holds if the walls are split at each crease.
+    fn assert_closed(surface: &RoofSurface) {
+        let polygon = rectangle();
+
+        let faces = surface.faces(&polygon).unwrap();
+        let area = faces
+            .iter()
+            .map(|(_, face)| face.unsigned_area())
+            .sum::<f32>();
+        assert!((area - polygon.unsigned_area()).abs() < 1e-2, "faces cover {area} m²");
+
+        let ring = &polygon.exterior().0[..4];
+        let split = surface.split_ring(ring);
+
+        for (i, &a) in split.iter().enumerate() {
+            let b = split[(i + 1) % split.len()];
+            let wall = (surface.height(a) + surface.height(b)) / 2.;
+            let roof = surface.height((a + b) / 2.);
+            assert!((wall - roof).abs() < 1e-3, "wall at {wall} below roof at {roof}");
+        }
+    }
+
+    #[test]
+    fn gabled() {
+        let surface = surface(RoofShape::Gabled);
+
+        assert_eq!(surface.walls, 10.);
+        assert_eq!(surface.planes.len(), 2);
+        assert_eq!(surface.faces(&rectangle()).unwrap().len(), 2);
+
+        // the ridge runs along the whole length, with gables at both ends
+        assert!((height(&surface, -10., 0.) - 13.).abs() < 1e-4);
+        assert!((height(&surface, 10., 0.) - 13.).abs() < 1e-4);
+        assert!((height(&surface, 0., 5.) - 10.).abs() < 1e-4);
+        assert!((height(&surface, 0., -2.5) - 11.5).abs() < 1e-4);
+        assert_eq!(surface.split_ring(&rectangle().exterior().0[..4]).len(), 6);
+
+        assert_closed(&surface);
+    }
+
+    #[test]
+    fn hipped() {
+        let surface = surface(RoofShape::Hipped);
+
+        assert_eq!(surface.planes.len(), 4);
+        assert_eq!(surface.faces(&rectangle()).unwrap().len(), 4);
+
+        // the ridge is shortened by the width of the building, and the ends slope down
+        assert!((height(&surface, 5., 0.) - 13.).abs() < 1e-4);
+        assert!((height(&surface, -5., 0.) - 13.).abs() < 1e-4);
+        assert!((height(&surface, 10., 0.) - 10.).abs() < 1e-4);
+        assert!((height(&surface, 0., 5.) - 10.).abs() < 1e-4);
+
+        assert_closed(&surface);
+    }
+
+    #[test]
+    fn skillion() {
+        let surface = surface(RoofShape::Skillion);
+
+        assert_eq!(surface.planes.len(), 1);
+        assert!(!surface.is_flat());
+
+        // a single slope from one long side to the other
+        let (low, high) = (height(&surface, 0., -5.), height(&surface, 0., 5.));
+        assert!((low.min(high) - 10.).abs() < 1e-4);
+        assert!((low.max(high) - 13.).abs() < 1e-4);
+        assert!((height(&surface, 0., 0.) - 11.5).abs() < 1e-4);
+
+        assert_closed(&surface);
+    }
+
+    #[test]
+    fn roof_height_included_in_building_height() {
+        let roof = Roof {
+            shape: RoofShape::Gabled,
+            height: Some(3.),
+            direction: None,
+            across: false,
+            included: true,
+        };
+        let surface = roof.surface(&rectangle(), 0.0..10.);
+
+        assert_eq!(surface.walls, 7.);
+        assert!((height(&surface, 0., 0.) - 10.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn flat_roof_height_added_to_levels() {
+        let tags = tags(&[("building:levels", "4"), ("roof:levels", "1")]);
+        let height = tags.building_height().unwrap();
+        let surface = Roof::from_tags(&tags).surface(&rectangle(), 0.0..height);
+
+        assert_eq!(height, 12.);
+        assert_eq!(surface.walls, 15.);
+        assert!(surface.is_flat());
+        assert_closed(&surface);
+    }
+
+    #[test]
+    fn flat_roof_height_included_in_building_height() {
+        let tags = tags(&[("height", "15"), ("roof:height", "3")]);
+        let height = tags.building_height().unwrap();
+        let surface = Roof::from_tags(&tags).surface(&rectangle(), 0.0..height);
+
+        assert_eq!(surface.walls, 15.);
+    }
+}
@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use geo::Point;
+
+use crate::config::{Config, Layer, PresentModeSetting};
+
+/// Explore OpenStreetMap data in 3D.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Where to open the map, as `lat,lon` or a `geo:` URI.
+    #[arg(value_parser = parse_location, allow_hyphen_values = true)]
+    pub origin: Option<Point>,
+
+    /// Config file to read instead of `$DARKMAP_CONFIG` or `darkmap.toml`.
+    #[arg(long, short)]
+    pub config: Option<PathBuf>,
+
+    /// Distance around the camera to load, in metres.
+    #[arg(long, short)]
+    pub radius: Option<f64>,
+
+    /// Layers to load, separated by commas.
+    #[arg(long, value_delimiter = ',')]
+    pub layers: Option<Vec<Layer>>,
+
+    /// Window size as `WIDTHxHEIGHT`.
+    #[arg(long, value_parser = parse_size)]
+    pub size: Option<(f32, f32)>,
+
+    #[arg(long)]
+    pub present_mode: Option<PresentModeSetting>,
+}
+
+impl Cli {
+    /// Overrides settings from the config file and environment with the ones given on the
+    /// command line.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(origin) = self.origin {
+            config.start.origin = origin;
+        }
+
+        if let Some(radius) = self.radius {
+            config.tiles.load_radius = radius;
+        }
+
+        if let Some(layers) = &self.layers {
+            config.layers = layers.clone();
+        }
+
+        if let Some((width, height)) = self.size {
+            config.window.width = width;
+            config.window.height = height;
+        }
+
+        if let Some(present_mode) = self.present_mode {
+            config.window.present_mode = present_mode;
+        }
+    }
+}
+
+/// Parses `lat,lon` or a `geo:` URI as described in RFC 5870, ignoring altitude and parameters.
+pub fn parse_location(s: &str) -> Result<Point, String> {
+    let coords = s.trim();
+    let coords = coords.strip_prefix("geo:").unwrap_or(coords);
+    let coords = coords.split([';', '?']).next().unwrap_or_default();
+
+    let mut parts = coords.split(',').map(|part| part.trim().parse::<f64>());
+
+    let (Some(Ok(lat)), Some(Ok(lon))) = (parts.next(), parts.next()) else {
+        return Err(format!("expected `lat,lon` or a `geo:` URI, got `{s}`"));
+    };
+
+    if !(-90. ..=90.).contains(&lat) || !(-180. ..=180.).contains(&lon) {
+        return Err(format!("coordinate out of range: {lat},{lon}"));
+    }
+
+    Ok(Point::new(lon, lat))
+}
+
+fn parse_size(s: &str) -> Result<(f32, f32), String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| format!("expected `WIDTHxHEIGHT`, got `{s}`"))?;
+
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<f32>()
+            .map_err(|e| format!("invalid size `{s}`: {e}"))
+    };
+
+    Ok((parse(width)?, parse(height)?))
+}
@@ -1,4 +1,9 @@
-use bevy::prelude::*;
+use bevy::{
+    ecs::query::ReadOnlyWorldQuery,
+    prelude::*,
+    tasks::{block_on, AsyncComputeTaskPool, Task},
+};
+use futures::future::poll_immediate;
 use geo::Point;
 
 #[derive(Component, Reflect)]
@@ -6,3 +11,33 @@ pub struct DecorateRequest;
 
 #[derive(Component)]
 pub struct WorldPosition(pub Point);
+
+/// Mesh being built in the background, added to the entity by [`insert_meshes`] once done.
+#[derive(Component)]
+pub struct MeshTask(Task<Option<Mesh>>);
+
+impl MeshTask {
+    pub fn spawn(build: impl FnOnce() -> Option<Mesh> + Send + 'static) -> Self {
+        Self(AsyncComputeTaskPool::get().spawn(async move { build() }))
+    }
+}
+
+/// Adds the finished meshes of entities matching the filter `F` to the world.
+pub fn insert_meshes<F: ReadOnlyWorldQuery>(
+    mut query: Query<(Entity, &mut MeshTask), F>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut commands: Commands,
+) {
+    for (entity, mut task) in &mut query {
+        let Some(mesh) = block_on(poll_immediate(&mut task.0)) else {
+            continue;
+        };
+
+        let mut cmds = commands.entity(entity);
+        cmds.remove::<MeshTask>();
+
+        if let Some(mesh) = mesh {
+            cmds.insert(meshes.add(mesh));
+        }
+    }
+}
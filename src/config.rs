@@ -0,0 +1,173 @@
+use std::{env, fs, io::ErrorKind, path::PathBuf, sync::OnceLock};
+
+use anyhow::Context;
+use bevy::window::PresentMode;
+use clap::ValueEnum;
+use geo::Point;
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    buildings::BuildingsConfig,
+    cli::{self, Cli},
+    decorate::DecorateConfig,
+    geocoder::GeocoderConfig,
+    loading::Source,
+    overpass::{cache::CacheConfig, ClientConfig},
+    tiles::TilesConfig,
+};
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Loads the global configuration with the command line applied on top. Called once at startup,
+/// before anything uses [`get`].
+pub fn init(cli: &Cli) -> anyhow::Result<&'static Config> {
+    let config = Config::load(cli)?;
+
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+/// Returns the global configuration.
+pub fn get() -> &'static Config {
+    CONFIG
+        .get()
+        .expect("Configuration used before it was loaded")
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Layers that are loaded and shown.
+    pub layers: Vec<Layer>,
+    pub start: StartConfig,
+    pub window: WindowConfig,
+    pub overpass: ClientConfig,
+    pub cache: CacheConfig,
+    pub source: Source,
+    pub tiles: TilesConfig,
+    pub decorate: DecorateConfig,
+    pub geocoder: GeocoderConfig,
+    pub buildings: BuildingsConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            layers: vec![Layer::Buildings, Layer::Roads, Layer::Poi],
+            start: StartConfig::default(),
+            window: WindowConfig::default(),
+            overpass: ClientConfig::default(),
+            cache: CacheConfig::default(),
+            source: Source::default(),
+            tiles: TilesConfig::default(),
+            decorate: DecorateConfig::default(),
+            geocoder: GeocoderConfig::default(),
+            buildings: BuildingsConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file given on the command line, at `$DARKMAP_CONFIG` or `darkmap.toml`
+    /// in the working directory, and applies environment variable and command line overrides on
+    /// top. A missing file is not an error, unless it was given on the command line.
+    fn load(cli: &Cli) -> anyhow::Result<Self> {
+        let required = cli.config.is_some();
+        let path = cli
+            .config
+            .clone()
+            .or_else(|| env::var_os("DARKMAP_CONFIG").map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from("darkmap.toml"));
+
+        let mut config: Config = match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound && !required => Config::default(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+
+        config.overpass.apply_env()?;
+        config.cache.apply_env();
+        config.source.apply_env();
+
+        cli.apply(&mut config);
+
+        Ok(config)
+    }
+
+    pub fn has_layer(&self, layer: Layer) -> bool {
+        self.layers.contains(&layer)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Layer {
+    Buildings,
+    Roads,
+    Poi,
+}
+
+/// Where the map opens, read from the `[start]` section of the config file.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct StartConfig {
+    /// `lat,lon` or a `geo:` URI.
+    #[serde(deserialize_with = "deserialize_location")]
+    pub origin: Point,
+}
+
+impl Default for StartConfig {
+    fn default() -> Self {
+        Self {
+            origin: Point::new(139.77137176176117, 35.69967697464613),
+        }
+    }
+}
+
+fn deserialize_location<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Point, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    cli::parse_location(&s).map_err(serde::de::Error::custom)
+}
+
+/// Read from the `[window]` section of the config file.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub width: f32,
+    pub height: f32,
+    pub present_mode: PresentModeSetting,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            width: 1920.,
+            height: 1080.,
+            present_mode: PresentModeSetting::AutoNoVsync,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum PresentModeSetting {
+    AutoVsync,
+    AutoNoVsync,
+    Fifo,
+    Immediate,
+    Mailbox,
+}
+
+impl From<PresentModeSetting> for PresentMode {
+    fn from(value: PresentModeSetting) -> Self {
+        match value {
+            PresentModeSetting::AutoVsync => PresentMode::AutoVsync,
+            PresentModeSetting::AutoNoVsync => PresentMode::AutoNoVsync,
+            PresentModeSetting::Fifo => PresentMode::Fifo,
+            PresentModeSetting::Immediate => PresentMode::Immediate,
+            PresentModeSetting::Mailbox => PresentMode::Mailbox,
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics, RegisterDiagnostic},
+    prelude::*,
+};
+use bevy_panorbit_camera::PanOrbitCamera;
+use serde::Deserialize;
+
+use crate::{
+    common::{DecorateRequest, WorldPosition},
+    viewport::{MainCamera, OriginCoordinate},
+};
+
+pub const DECORATE_QUEUE_DIAGNOSTIC: DiagnosticId =
+    DiagnosticId::from_u128(0x5C2B8E0D_37A4_4F0B_9E61_0B4D2A7C91E3);
+
+pub const DECORATE_TIME_DIAGNOSTIC: DiagnosticId =
+    DiagnosticId::from_u128(0x8A41F6C2_94D3_4E7A_B1C5_6F2E03D8A47B);
+
+pub const DECORATE_TIME_DIAGNOSTIC_SUFFIX: &str = "us";
+
+/// Hands out [`DecorateRequest`]s a few at a time, nearest to the camera focus first, so that
+/// decorating takes about the same time every frame regardless of how much was just loaded.
+/// Decorating systems stop once the frame's budget is used up and continue in the next frame.
+#[derive(Default)]
+pub struct DecoratePlugin;
+
+impl Plugin for DecoratePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DecorateQueue>()
+            .register_diagnostic(Diagnostic::new(DECORATE_QUEUE_DIAGNOSTIC, "decorate queue", 10))
+            .register_diagnostic(
+                Diagnostic::new(DECORATE_TIME_DIAGNOSTIC, "decorate time", 10)
+                    .with_suffix(DECORATE_TIME_DIAGNOSTIC_SUFFIX),
+            )
+            .add_systems(
+                Update,
+                (schedule_decorations.before(DecorateSet), report_decorations.after(DecorateSet)),
+            );
+    }
+}
+
+/// Settings for the decoration scheduler, read from the `[decorate]` section of the config file.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct DecorateConfig {
+    /// Time spent decorating entities per frame, in milliseconds.
+    pub budget: f64,
+}
+
+impl Default for DecorateConfig {
+    fn default() -> Self {
+        Self { budget: 4. }
+    }
+}
+
+/// Systems that decorate entities marked with [`Decorate`].
+#[derive(SystemSet, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DecorateSet;
+
+/// Marks an entity that was picked from the queue to be decorated this frame. Decorating systems
+/// should remove it once they're done with the entity.
+#[derive(Component)]
+pub struct Decorate;
+
+#[derive(Resource)]
+pub struct DecorateQueue {
+    /// Running average of the time it takes to decorate one entity.
+    cost: Duration,
+    budget: Duration,
+    used: Duration,
+    done: usize,
+    len: usize,
+}
+
+impl Default for DecorateQueue {
+    fn default() -> Self {
+        Self {
+            cost: Duration::from_micros(100),
+            budget: Duration::ZERO,
+            used: Duration::ZERO,
+            done: 0,
+            len: 0,
+        }
+    }
+}
+
+impl DecorateQueue {
+    /// Whether there's time left this frame to decorate another entity, after the calling system
+    /// already spent `elapsed` on top of what the others reported.
+    pub fn has_time(&self, elapsed: Duration) -> bool {
+        self.used + elapsed < self.budget
+    }
+
+    /// Reports the time a system spent decorating `count` entities.
+    pub fn record(&mut self, elapsed: Duration, count: usize) {
+        self.used += elapsed;
+        self.done += count;
+    }
+}
+
+fn schedule_decorations(
+    query: Query<(Entity, &WorldPosition), (With<DecorateRequest>, Without<Decorate>)>,
+    pending: Query<(), With<Decorate>>,
+    camera: Query<&PanOrbitCamera, With<MainCamera>>,
+    origin: Res<OriginCoordinate>,
+    mut queue: ResMut<DecorateQueue>,
+    mut commands: Commands,
+) {
+    queue.budget = Duration::from_secs_f64(crate::config::get().decorate.budget / 1000.);
+    queue.len = query.iter().count();
+
+    if queue.len == 0 {
+        return;
+    }
+
+    // entities left over from the last frame go first
+    let count = (queue.budget.as_secs_f64() / queue.cost.as_secs_f64().max(1e-6)).ceil() as usize;
+    let count = count.saturating_sub(pending.iter().count());
+
+    let focus = camera
+        .get_single()
+        .map(|camera| camera.focus)
+        .unwrap_or_default();
+
+    let mut queued = query
+        .iter()
+        .map(|(entity, WorldPosition(point))| {
+            (entity, origin.to_world(*point).distance_squared(focus))
+        })
+        .collect::<Vec<_>>();
+
+    if count < queued.len() {
+        queued.select_nth_unstable_by(count, |a, b| a.1.total_cmp(&b.1));
+        queued.truncate(count);
+    }
+
+    for (entity, _) in queued {
+        commands
+            .entity(entity)
+            .remove::<DecorateRequest>()
+            .insert(Decorate);
+    }
+}
+
+fn report_decorations(mut queue: ResMut<DecorateQueue>, mut diagnostics: Diagnostics) {
+    if queue.done > 0 {
+        let cost = queue.used / queue.done as u32;
+        queue.cost = (queue.cost * 4 + cost) / 5;
+    }
+
+    let used = queue.used;
+    let len = queue.len;
+
+    diagnostics.add_measurement(DECORATE_TIME_DIAGNOSTIC, || used.as_secs_f64() * 1_000_000.);
+    diagnostics.add_measurement(DECORATE_QUEUE_DIAGNOSTIC, || len as f64);
+
+    queue.used = Duration::ZERO;
+    queue.done = 0;
+}
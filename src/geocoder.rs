@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use geo::Point;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Deserializer};
+
+use crate::overpass::ClientConfig;
+
+lazy_static! {
+    static ref CLIENT: surf::Client = {
+        let config = crate::config::get();
+        config
+            .geocoder
+            .client(&config.overpass)
+            .expect("Failed to create geocoder client")
+    };
+}
+
+/// Settings for searching places by name, read from the `[geocoder]` section of the config file.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct GeocoderConfig {
+    /// Base URL of a Nominatim-compatible geocoder, without the `/search` path.
+    pub endpoint: String,
+    /// Maximum number of results per search.
+    pub limit: usize,
+    /// Per-request timeout in seconds, or no timeout if unset.
+    pub timeout: Option<f64>,
+    /// Extra headers sent with every request.
+    pub headers: HashMap<String, String>,
+}
+
+impl Default for GeocoderConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "https://nominatim.openstreetmap.org".to_string(),
+            limit: 10,
+            timeout: Some(10.),
+            headers: HashMap::new(),
+        }
+    }
+}
+
+impl GeocoderConfig {
+    /// Identifies itself with the same user agent as Overpass queries, but leaves out the Overpass
+    /// headers, which may hold credentials for a private instance.
+    fn client(&self, overpass: &ClientConfig) -> anyhow::Result<surf::Client> {
+        ClientConfig {
+            timeout: self.timeout,
+            user_agent: overpass.user_agent.clone(),
+            headers: self.headers.clone(),
+            ..ClientConfig::default()
+        }
+        .client()
+    }
+}
+
+/// A search result in Nominatim's `jsonv2` format.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Place {
+    pub display_name: String,
+    #[serde(default)]
+    pub category: String,
+    #[serde(default, rename = "type")]
+    pub kind: String,
+    #[serde(deserialize_with = "string_f64")]
+    pub lat: f64,
+    #[serde(deserialize_with = "string_f64")]
+    pub lon: f64,
+}
+
+impl Place {
+    pub fn point(&self) -> Point {
+        Point::new(self.lon, self.lat)
+    }
+}
+
+/// Nominatim returns coordinates as strings.
+fn string_f64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+pub async fn search(query: &str) -> anyhow::Result<Vec<Place>> {
+    let config = &crate::config::get().geocoder;
+
+    let url = format!(
+        "{}/search?format=jsonv2&limit={}&q={}",
+        config.endpoint.trim_end_matches('/'),
+        config.limit,
+        urlencoding::encode(query),
+    );
+
+    let mut res = CLIENT
+        .get(&url)
+        .await
+        .map_err(|e| e.into_inner())
+        .context("Failed to reach the geocoder")?;
+
+    let body = res.body_string().await.map_err(|e| e.into_inner())?;
+
+    if !res.status().is_success() {
+        anyhow::bail!("Geocoder returned status {}: {}", res.status(), body.trim());
+    }
+
+    serde_json::from_str(&body).context("Failed to parse geocoder response")
+}
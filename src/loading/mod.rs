@@ -1,11 +1,26 @@
-use std::marker::{PhantomData, Send};
+mod registry;
+mod source;
+
+use std::{
+    any::TypeId,
+    collections::HashSet,
+    marker::{PhantomData, Send},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use bevy::{
     prelude::*,
     tasks::{block_on, AsyncComputeTaskPool, Task},
 };
 use futures::{future::poll_immediate, Future};
-use geo::{HaversineDestination, Point};
+use geo::{HaversineDestination, Point, Rect};
+
+pub use self::source::{fetch, Source};
+use crate::overpass::{OsmId, Selector};
 
 #[derive(Default)]
 pub struct LoadingPlugin<T: LoadType> {
@@ -20,87 +35,297 @@ impl<T: LoadType> LoadingPlugin<T> {
 
 impl<T: LoadType> Plugin for LoadingPlugin<T> {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (start_loading::<T>, finish_loading::<T>));
+        source::add_layer::<T>();
+
+        app.init_resource::<ActiveLoads>()
+            .init_resource::<registry::OsmRegistry<T>>()
+            .add_event::<LoadStarted<T>>()
+            .add_event::<LoadFinished<T>>()
+            .add_event::<LoadFailed<T>>()
+            .add_event::<LoadCancelled<T>>()
+            .add_systems(PreUpdate, registry::forget_elements::<T>)
+            .add_systems(
+                Update,
+                (
+                    start_loading::<T>,
+                    finish_loading::<T>,
+                    cancel_loading::<T>.after(finish_loading::<T>),
+                ),
+            );
     }
 }
 
 #[derive(Component)]
 pub struct LoadRequest {
-    center: Point,
-    radius: f64,
-    // _marker: PhantomData<T>,
+    rect: Rect,
+    layer: Option<TypeId>,
 }
 
 impl LoadRequest {
     pub fn new(center: Point, radius: f64) -> Self {
-        Self { center, radius }
+        let north = center.haversine_destination(0., radius);
+        let east = center.haversine_destination(90., radius);
+        let south = center.haversine_destination(180., radius);
+        let west = center.haversine_destination(270., radius);
+
+        Self::from_rect(Rect::new((west.x(), south.y()), (east.x(), north.y())))
+    }
+
+    pub fn from_rect(rect: Rect) -> Self {
+        Self { rect, layer: None }
+    }
+
+    /// Restricts the request to a single layer, like when retrying one that failed.
+    pub fn only<T: LoadType>(mut self) -> Self {
+        self.layer = Some(TypeId::of::<T>());
+        self
+    }
+
+    fn includes<T: LoadType>(&self) -> bool {
+        self.layer.map_or(true, |layer| layer == TypeId::of::<T>())
+    }
+
+    pub fn rect(&self) -> Rect {
+        self.rect
     }
 
     pub fn bbox(&self) -> String {
-        let north = self.center.haversine_destination(0., self.radius);
-        let east = self.center.haversine_destination(90., self.radius);
-        let south = self.center.haversine_destination(180., self.radius);
-        let west = self.center.haversine_destination(270., self.radius);
+        let rect = self.rect();
 
-        format!("{},{},{},{}", south.y(), west.x(), north.y(), east.x())
+        format!("{},{},{},{}", rect.min().y, rect.min().x, rect.max().y, rect.max().x)
     }
 }
 
 impl Clone for LoadRequest {
     fn clone(&self) -> Self {
+        Self { rect: self.rect, layer: self.layer }
+    }
+}
+
+/// Progress of loading one layer for the entity that requested it.
+#[derive(Component)]
+pub struct LoadState<T: LoadType> {
+    pub status: LoadStatus,
+    pub rect: Rect,
+    pub started: Instant,
+    /// Set once the load has finished, successfully or not.
+    pub duration: Option<Duration>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: LoadType> LoadState<T> {
+    fn new(rect: Rect) -> Self {
         Self {
-            center: self.center,
-            radius: self.radius,
+            status: LoadStatus::Loading,
+            rect,
+            started: Instant::now(),
+            duration: None,
+            _marker: PhantomData,
         }
     }
+
+    /// Time spent loading so far, or in total once finished.
+    pub fn elapsed(&self) -> Duration {
+        self.duration.unwrap_or_else(|| self.started.elapsed())
+    }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadStatus {
+    Loading,
+    Loaded {
+        count: usize,
+    },
+    /// The error followed by each of its causes.
+    Failed {
+        errors: Vec<String>,
+    },
+}
+
+/// Loads one layer in the background. Removing or replacing this component, or despawning its
+/// entity, drops the task and so cancels the load at its next await point.
 #[derive(Component)]
 pub struct LoadTask<T: LoadType> {
-    task: Task<anyhow::Result<Vec<T::Bundle>>>,
+    task: Task<anyhow::Result<Vec<(OsmId, T::Bundle)>>>,
+    _active: ActiveLoad,
+}
+
+/// Number of load tasks currently running, across all layers.
+#[derive(Resource, Clone, Default)]
+pub struct ActiveLoads(Arc<AtomicUsize>);
+
+impl ActiveLoads {
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn start(&self) -> ActiveLoad {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        ActiveLoad(self.0.clone())
+    }
+}
+
+/// Counts as an active load until dropped, which also covers tasks dropped with their entity.
+struct ActiveLoad(Arc<AtomicUsize>);
+
+impl Drop for ActiveLoad {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
+/// Sent when a layer starts loading for an entity.
+#[derive(Event)]
+pub struct LoadStarted<T: LoadType> {
+    pub entity: Entity,
+    pub rect: Rect,
+    _marker: PhantomData<T>,
+}
+
+/// Sent once the loaded elements of a layer have been spawned, which happens when the commands of
+/// this frame are applied.
+#[derive(Event)]
+pub struct LoadFinished<T: LoadType> {
+    pub entity: Entity,
+    pub count: usize,
+    _marker: PhantomData<T>,
+}
+
+#[derive(Event)]
+pub struct LoadFailed<T: LoadType> {
+    pub entity: Entity,
+    /// The error followed by each of its causes.
+    pub errors: Vec<String>,
+    _marker: PhantomData<T>,
+}
+
+/// Sent when a load was dropped before it finished, because its task was removed or its entity
+/// was despawned.
+#[derive(Event)]
+pub struct LoadCancelled<T: LoadType> {
+    pub entity: Entity,
+    _marker: PhantomData<T>,
+}
+
+/// Starts loading the layer for every new request that includes it. A request for an entity that
+/// is still loading the layer replaces the running task, which is dropped and so cancelled.
 fn start_loading<T: LoadType>(
-    query: Query<(Entity, &LoadRequest), Without<LoadTask<T>>>,
+    query: Query<(Entity, &LoadRequest, Has<LoadTask<T>>)>,
+    active: Res<ActiveLoads>,
+    mut started: EventWriter<LoadStarted<T>>,
     mut commands: Commands,
 ) {
-    for (entity, req) in &mut query.iter() {
+    for (entity, req, running) in &mut query.iter() {
+        if !req.includes::<T>() {
+            continue;
+        }
+
+        if running {
+            debug!("Restarting loading {}", std::any::type_name::<T>());
+        }
+
         let req = req.clone();
+        let rect = req.rect;
         let task = AsyncComputeTaskPool::get().spawn(async move { T::load(req).await });
 
         commands
             .entity(entity)
-            .insert(LoadTask::<T> { task })
+            .insert((LoadTask::<T> { task, _active: active.start() }, LoadState::<T>::new(rect)))
             .remove::<LoadRequest>();
+
+        started.send(LoadStarted { entity, rect, _marker: PhantomData });
     }
 }
 
 fn finish_loading<T: LoadType>(
-    mut query: Query<(Entity, &mut LoadTask<T>)>,
+    mut query: Query<(Entity, &mut LoadTask<T>, &mut LoadState<T>, Option<&LoadRequest>)>,
+    children: Query<&Children>,
+    mut registry: ResMut<registry::OsmRegistry<T>>,
+    mut finished: EventWriter<LoadFinished<T>>,
+    mut failed: EventWriter<LoadFailed<T>>,
     mut commands: Commands,
 ) {
-    for (entity, mut task) in &mut query.iter_mut() {
+    for (entity, mut task, mut state, req) in &mut query.iter_mut() {
+        // about to be replaced by `start_loading`
+        if req.is_some_and(LoadRequest::includes::<T>) {
+            continue;
+        }
+
         if let Some(result) = block_on(poll_immediate(&mut task.task)) {
             commands.entity(entity).remove::<LoadTask<T>>();
 
-            match result {
-                Ok(bundles) => {
-                    for bundle in bundles {
-                        commands.spawn(bundle);
-                    }
+            state.duration = Some(state.started.elapsed());
+            state.status = match result {
+                Ok(elements) => {
+                    let count = elements.len();
+                    registry.spawn(elements, &children, &mut commands);
+                    finished.send(LoadFinished { entity, count, _marker: PhantomData });
+                    LoadStatus::Loaded { count }
                 }
                 Err(e) => {
                     error!("Failed to load: {}", e);
                     for cause in e.chain() {
                         error!("Caused by: {}", cause);
                     }
+                    let errors = e.chain().map(|cause| cause.to_string()).collect::<Vec<_>>();
+                    failed.send(LoadFailed {
+                        entity,
+                        errors: errors.clone(),
+                        _marker: PhantomData,
+                    });
+                    LoadStatus::Failed { errors }
                 }
-            }
+            };
+        }
+    }
+}
+
+/// Reports loads whose task went away before finishing, and clears their state if the entity is
+/// still around so it no longer shows as loading.
+///
+/// Runs after `finish_loading` and keeps track of which entities are still loading, as by the
+/// time a removal is seen the entity may be gone along with its state.
+fn cancel_loading<T: LoadType>(
+    changed: Query<(Entity, &LoadState<T>), Changed<LoadState<T>>>,
+    mut removed: RemovedComponents<LoadTask<T>>,
+    mut loading: Local<HashSet<Entity>>,
+    mut cancelled: EventWriter<LoadCancelled<T>>,
+    mut commands: Commands,
+) {
+    for (entity, state) in &changed {
+        if state.status == LoadStatus::Loading {
+            loading.insert(entity);
+        } else {
+            loading.remove(&entity);
+        }
+    }
+
+    for entity in removed.read() {
+        if !loading.remove(&entity) {
+            continue;
         }
+
+        if let Some(mut entity_commands) = commands.get_entity(entity) {
+            entity_commands.remove::<LoadState<T>>();
+        }
+
+        debug!("Cancelled loading {}", std::any::type_name::<T>());
+        cancelled.send(LoadCancelled { entity, _marker: PhantomData });
     }
 }
 
-pub trait LoadType: Sized + Send + Sync + 'static {
+/// A layer of map features, which is also the component that marks the entities it spawns.
+pub trait LoadType: Component + Sized {
+    /// Overpass query for the layer, with `{{bbox}}` in place of the area.
+    const QUERY: &'static str;
+    /// Same filter as [`Self::QUERY`], for sources that can't run Overpass queries, for building
+    /// the combined query and for picking the layer out of its response.
+    const SELECTORS: &'static [Selector];
+
     type Bundle: Bundle;
-    fn load(req: LoadRequest) -> impl Future<Output = anyhow::Result<Vec<Self::Bundle>>> + Send;
+    /// Loads the elements of the layer in the area, each with the id of the OSM element it was
+    /// created from.
+    fn load(
+        req: LoadRequest,
+    ) -> impl Future<Output = anyhow::Result<Vec<(OsmId, Self::Bundle)>>> + Send;
 }
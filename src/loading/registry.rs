@@ -0,0 +1,83 @@
+use std::{collections::HashMap, marker::PhantomData};
+
+use bevy::prelude::*;
+
+use super::LoadType;
+use crate::overpass::{ElementKind, OsmId};
+
+/// Entities spawned for each OSM element by one layer, so an element loaded again by an
+/// overlapping request doesn't end up in the world twice. Each layer has its own registry, as the
+/// same element can be both a building and a road.
+#[derive(Resource)]
+pub struct OsmRegistry<T: LoadType> {
+    elements: HashMap<(ElementKind, i64), (Entity, Option<u32>)>,
+    keys: HashMap<Entity, (ElementKind, i64)>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: LoadType> Default for OsmRegistry<T> {
+    fn default() -> Self {
+        Self {
+            elements: HashMap::new(),
+            keys: HashMap::new(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: LoadType> OsmRegistry<T> {
+    /// Spawns loaded elements unless they already exist in the same or a newer version. Older
+    /// versions are despawned and replaced, handing the entities parented to them, like POIs on
+    /// buildings, over to the replacement.
+    pub(super) fn spawn(
+        &mut self,
+        elements: Vec<(OsmId, T::Bundle)>,
+        children: &Query<&Children>,
+        commands: &mut Commands,
+    ) {
+        for (osm_id, bundle) in elements {
+            let key = (osm_id.kind, osm_id.id);
+
+            let replaced = match self.elements.get(&key) {
+                // unknown versions are assumed to be the same
+                Some(&(_, version)) if osm_id.version.is_none() || osm_id.version <= version => {
+                    continue;
+                }
+                Some(&(existing, _)) => Some(existing),
+                None => None,
+            };
+
+            let entity = commands.spawn((osm_id, bundle)).id();
+
+            if let Some(existing) = replaced {
+                if let Ok(children) = children.get(existing) {
+                    commands.entity(entity).push_children(children);
+                }
+
+                commands.entity(existing).despawn_recursive();
+                self.keys.remove(&existing);
+            }
+
+            self.elements.insert(key, (entity, osm_id.version));
+            self.keys.insert(entity, key);
+        }
+    }
+}
+
+/// Forgets elements whose entities were despawned, so they're spawned again when loaded again.
+pub(super) fn forget_elements<T: LoadType>(
+    mut removed: RemovedComponents<OsmId>,
+    mut registry: ResMut<OsmRegistry<T>>,
+) {
+    for entity in removed.read() {
+        if let Some(key) = registry.keys.remove(&entity) {
+            if registry
+                .elements
+                .get(&key)
+                .is_some_and(|(e, _)| *e == entity)
+            {
+                registry.elements.remove(&key);
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use bevy::prelude::*;
+use futures::future::{BoxFuture, FutureExt, Shared, WeakShared};
+use geo::Rect;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{LoadRequest, LoadType};
+use crate::overpass::{self, ApiResponse, Selector};
+
+type CombinedLoad =
+    BoxFuture<'static, Result<Arc<HashMap<TypeId, ApiResponse>>, Arc<overpass::Error>>>;
+
+lazy_static! {
+    /// Combined queries that are still being awaited by at least one layer, by bbox.
+    static ref COMBINED: Mutex<HashMap<String, WeakShared<CombinedLoad>>> = Mutex::default();
+    /// The layers that were added to the app, which make up the combined query.
+    static ref LAYERS: Mutex<Vec<Layer>> = Mutex::default();
+}
+
+#[derive(Clone, Copy)]
+struct Layer {
+    id: TypeId,
+    selectors: &'static [Selector],
+}
+
+/// Includes the layer in the combined query.
+pub(super) fn add_layer<T: LoadType>() {
+    let mut layers = LAYERS.lock().unwrap();
+
+    if !layers.iter().any(|layer| layer.id == TypeId::of::<T>()) {
+        layers.push(Layer {
+            id: TypeId::of::<T>(),
+            selectors: T::SELECTORS,
+        });
+    }
+}
+
+/// Where map data is loaded from, read from the `[source]` section of the config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Source {
+    /// Run the combined or the layer's own `.ovp` query against the configured Overpass endpoint.
+    #[default]
+    Overpass,
+    /// Read from a local `.osm.pbf` extract, filtering with the layer's selectors.
+    Pbf { path: PathBuf },
+    /// Read from a local OSM XML file, e.g. a JOSM export, filtering with the layer's selectors.
+    Osm { path: PathBuf },
+}
+
+impl Source {
+    pub(crate) fn apply_env(&mut self) {
+        if let Some(path) = std::env::var_os("DARKMAP_PBF") {
+            *self = Source::Pbf { path: path.into() };
+        } else if let Some(path) = std::env::var_os("DARKMAP_OSM") {
+            *self = Source::Osm { path: path.into() };
+        }
+    }
+}
+
+/// Loads the elements for one layer from the configured source, either by running the layer's
+/// query or, for sources that can't run it, by filtering with its selectors.
+pub async fn fetch<T: LoadType>(req: &LoadRequest) -> anyhow::Result<ApiResponse> {
+    match &crate::config::get().source {
+        // a request for a single layer, like a retry, doesn't need the others
+        Source::Overpass if crate::config::get().overpass.combine && req.layer.is_none() => {
+            let layers = combined(req).await?;
+
+            layers
+                .get(&TypeId::of::<T>())
+                .cloned()
+                .context("Layer is missing from the combined query")
+        }
+        Source::Overpass => Ok(load(render(T::QUERY, req)?, req.rect()).await?),
+        Source::Pbf { path } => overpass::pbf::load(path, req.rect(), T::SELECTORS)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display())),
+        Source::Osm { path } => overpass::xml::load(path, req.rect(), T::SELECTORS)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+fn render(template: &str, req: &LoadRequest) -> anyhow::Result<String> {
+    handlebars::Handlebars::new()
+        .render_template(template, &json!({ "bbox": req.bbox() }))
+        .context("Failed to render query")
+}
+
+async fn load(query: String, rect: Rect) -> Result<ApiResponse, overpass::Error> {
+    match overpass::load(&query, rect).await {
+        // better to show what we have than nothing at all
+        Err(overpass::Error::Remark { message, partial }) => {
+            warn!("Overpass returned incomplete data: {}", message);
+            Ok(*partial)
+        }
+        res => res,
+    }
+}
+
+/// Runs the query for all layers at once, or joins the one already running for the same area.
+/// All layers of an area start loading in the same frame, so they end up sharing one request. The
+/// response is split up by layer once it arrives.
+fn combined(req: &LoadRequest) -> Shared<CombinedLoad> {
+    let mut running = COMBINED.lock().unwrap();
+    let bbox = req.bbox();
+
+    if let Some(shared) = running.get(&bbox).and_then(WeakShared::upgrade) {
+        return shared;
+    }
+
+    let layers = LAYERS.lock().unwrap().clone();
+    let query = combined_query(&layers, &bbox);
+    let rect = req.rect();
+    let selectors = layers
+        .iter()
+        .map(|layer| (layer.id, layer.selectors))
+        .collect::<Vec<_>>();
+
+    let shared = async move {
+        load(query, rect)
+            .await
+            .map(|res| Arc::new(res.partition(&selectors)))
+            .map_err(Arc::new)
+    }
+    .boxed()
+    .shared();
+
+    running.retain(|_, weak| weak.upgrade().is_some());
+    if let Some(weak) = shared.downgrade() {
+        running.insert(bbox, weak);
+    }
+
+    shared
+}
+
+/// Selects the elements of every layer in a single union, built from the layers' selectors.
+fn combined_query(layers: &[Layer], bbox: &str) -> String {
+    let statements = layers
+        .iter()
+        .flat_map(|layer| layer.selectors)
+        .map(|selector| format!("  {}\n", selector.statement(bbox)))
+        .collect::<String>();
+
+    format!("[out:json];\n(\n{statements});\nout meta geom;\n")
+}
@@ -6,28 +6,36 @@
 #![allow(clippy::type_complexity)]
 
 mod buildings;
+mod cli;
 mod common;
+mod config;
 mod debug;
+mod decorate;
+mod geocoder;
 mod loading;
 mod overpass;
 mod poi;
+mod projection;
 mod roads;
+mod tiles;
 mod ui;
 mod viewport;
 
 use bevy::{
     diagnostic::{EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin},
     prelude::*,
-    window::{PresentMode, WindowResolution},
+    window::WindowResolution,
 };
 use bevy_egui::EguiPlugin;
 use bevy_mod_outline::OutlinePlugin;
 use bevy_mod_picking::DefaultPickingPlugins;
 use catppuccin::{Colour, Flavour, FlavourColours};
+use clap::Parser;
 
 use self::{
-    buildings::BuildingsPlugin, debug::DebugPlugin, poi::PoiPlugin, roads::RoadsPlugin,
-    ui::UiPlugin, viewport::ViewportPlugin,
+    buildings::BuildingsPlugin, cli::Cli, config::Layer, debug::DebugPlugin,
+    decorate::DecoratePlugin, poi::PoiPlugin, roads::RoadsPlugin, tiles::TilesPlugin, ui::UiPlugin,
+    viewport::ViewportPlugin,
 };
 
 const COLORS: FlavourColours = Flavour::Frappe.colours();
@@ -39,24 +47,41 @@ fn color(color: Colour) -> Color {
 }
 
 fn main() {
-    App::new()
-        .add_plugins((
-            DefaultPlugins.set(WindowPlugin {
-                primary_window: Some(Window {
-                    resolution: WindowResolution::new(1920., 1080.),
-                    present_mode: PresentMode::AutoNoVsync,
-                    ..default()
-                }),
+    let cli = Cli::parse();
+    let config = config::init(&cli).expect("Failed to load configuration");
+
+    let mut app = App::new();
+
+    app.add_plugins((
+        DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                resolution: WindowResolution::new(config.window.width, config.window.height),
+                present_mode: config.window.present_mode.into(),
                 ..default()
             }),
-            DefaultPickingPlugins,
-            EguiPlugin,
-            OutlinePlugin,
-            UiPlugin,
-            DebugPlugin,
-        ))
-        .add_plugins((FrameTimeDiagnosticsPlugin, EntityCountDiagnosticsPlugin))
-        .add_plugins((BuildingsPlugin, PoiPlugin, RoadsPlugin, ViewportPlugin))
-        .add_systems(Update, bevy::window::close_on_esc)
-        .run();
+            ..default()
+        }),
+        DefaultPickingPlugins,
+        EguiPlugin,
+        OutlinePlugin,
+        UiPlugin,
+        DebugPlugin,
+    ))
+    .add_plugins((FrameTimeDiagnosticsPlugin, EntityCountDiagnosticsPlugin))
+    .add_plugins((DecoratePlugin, TilesPlugin, ViewportPlugin))
+    .add_systems(Update, bevy::window::close_on_esc);
+
+    if config.has_layer(Layer::Buildings) {
+        app.add_plugins(BuildingsPlugin);
+    }
+
+    if config.has_layer(Layer::Roads) {
+        app.add_plugins(RoadsPlugin);
+    }
+
+    if config.has_layer(Layer::Poi) {
+        app.add_plugins(PoiPlugin);
+    }
+
+    app.run();
 }
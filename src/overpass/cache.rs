@@ -0,0 +1,385 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use bevy::prelude::*;
+use geo::{coord, Rect};
+use serde::Deserialize;
+
+/// Settings for the on-disk response cache, read from the `[cache]` section of the config file.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// Defaults to `darkmap/overpass` in the XDG cache directory.
+    pub dir: Option<PathBuf>,
+    /// Maximum age of a cached response in seconds before it is downloaded again.
+    pub max_age: Option<f64>,
+    /// Maximum total size of the cache in bytes; the oldest entries are removed first.
+    pub max_size: u64,
+    /// Only serve responses from the cache, never touch the network.
+    pub offline: bool,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: None,
+            max_age: Some(7. * 24. * 60. * 60.),
+            max_size: 512 * 1024 * 1024,
+            offline: false,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub(crate) fn apply_env(&mut self) {
+        if let Some(dir) = std::env::var_os("DARKMAP_CACHE_DIR") {
+            self.dir = Some(dir.into());
+        }
+
+        if std::env::var("DARKMAP_OFFLINE").is_ok_and(|v| !v.is_empty() && v != "0") {
+            self.offline = true;
+        }
+    }
+
+    fn dir(&self) -> Option<PathBuf> {
+        self.dir
+            .clone()
+            .or_else(|| dirs::cache_dir().map(|dir| dir.join("darkmap").join("overpass")))
+    }
+}
+
+fn config() -> &'static CacheConfig {
+    &crate::config::get().cache
+}
+
+/// Cache entries are keyed by a stable hash of the full query text, so any change to a template
+/// or bbox results in a new entry. The query and the area it covers are stored next to the
+/// response so entries can be found again by area.
+fn key(query: &str) -> String {
+    // FNV-1a, which unlike `DefaultHasher` is stable across builds
+    let hash = query
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+
+    format!("{hash:016x}")
+}
+
+fn paths(dir: &Path, query: &str) -> (PathBuf, PathBuf) {
+    let key = key(query);
+
+    (dir.join(format!("{key}.query")), dir.join(format!("{key}.body")))
+}
+
+/// Area as `min_lon,min_lat,max_lon,max_lat`, which round-trips exactly.
+fn format_rect(rect: Rect) -> String {
+    format!("{},{},{},{}", rect.min().x, rect.min().y, rect.max().x, rect.max().y)
+}
+
+fn parse_rect(s: &str) -> Option<Rect> {
+    let [min_x, min_y, max_x, max_y] = s
+        .trim()
+        .split(',')
+        .map(|n| n.parse::<f64>().ok())
+        .collect::<Option<Vec<_>>>()?
+        .try_into()
+        .ok()?;
+
+    Some(Rect::new(coord! { x: min_x, y: min_y }, coord! { x: max_x, y: max_y }))
+}
+
+/// Whether two areas share more than an edge, so neighbouring tiles don't count.
+fn overlaps(a: Rect, b: Rect) -> bool {
+    a.min().x < b.max().x && b.min().x < a.max().x && a.min().y < b.max().y && b.min().y < a.max().y
+}
+
+pub fn is_offline() -> bool {
+    config().offline
+}
+
+/// Returns the cached response body for this query, if there is one that hasn't expired.
+/// Expired entries are still returned in offline mode.
+pub async fn get(query: &str) -> Option<String> {
+    let query = query.to_string();
+
+    blocking::unblock(move || read(config(), &query)).await
+}
+
+fn read(config: &CacheConfig, query: &str) -> Option<String> {
+    if !config.enabled && !config.offline {
+        return None;
+    }
+
+    let (_, body_path) = paths(&config.dir()?, query);
+    let modified = fs::metadata(&body_path).and_then(|m| m.modified()).ok()?;
+
+    if !config.offline {
+        if let Some(max_age) = config.max_age {
+            let age = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or(Duration::ZERO);
+
+            if age > Duration::from_secs_f64(max_age) {
+                return None;
+            }
+        }
+    }
+
+    fs::read_to_string(body_path).ok()
+}
+
+/// Stores the response to a query covering `rect`.
+pub async fn put(query: &str, rect: Rect, body: &str) {
+    if !config().enabled {
+        return;
+    }
+
+    let (query, body) = (query.to_string(), body.to_string());
+
+    if let Err(e) = blocking::unblock(move || write(config(), &query, rect, &body)).await {
+        warn!("Failed to write Overpass response to cache: {}", e);
+    }
+}
+
+fn write(config: &CacheConfig, query: &str, rect: Rect, body: &str) -> io::Result<()> {
+    let Some(dir) = config.dir() else {
+        return Ok(());
+    };
+
+    let (query_path, body_path) = paths(&dir, query);
+
+    fs::create_dir_all(&dir)?;
+    fs::write(query_path, query)?;
+    fs::write(body_path.with_extension("bbox"), format_rect(rect))?;
+    fs::write(body_path, body)?;
+
+    prune(&dir, config.max_size)
+}
+
+/// Removes the oldest entries until the cache fits within `max_size` bytes.
+fn prune(dir: &Path, max_size: u64) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "body"))
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok()?;
+            Some((entry.path(), meta.len(), meta.modified().ok()?))
+        })
+        .collect::<Vec<_>>();
+
+    let mut total = entries.iter().map(|(_, len, _)| len).sum::<u64>();
+    if total <= max_size {
+        return Ok(());
+    }
+
+    entries.sort_by_key(|(_, _, modified)| *modified);
+
+    for (path, len, _) in entries {
+        if total <= max_size {
+            break;
+        }
+
+        remove_entry(&path)?;
+        total -= len;
+    }
+
+    Ok(())
+}
+
+fn remove_entry(body_path: &Path) -> io::Result<()> {
+    for path in [
+        body_path.with_extension("query"),
+        body_path.with_extension("bbox"),
+        body_path.to_path_buf(),
+    ] {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes every cached response whose area overlaps `rect`. Returns the number of removed
+/// entries.
+pub fn invalidate(rect: Rect) -> io::Result<usize> {
+    match config().dir() {
+        Some(dir) => invalidate_in(&dir, rect),
+        None => Ok(0),
+    }
+}
+
+fn invalidate_in(dir: &Path, rect: Rect) -> io::Result<usize> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut removed = 0;
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if !path.extension().is_some_and(|ext| ext == "bbox") {
+            continue;
+        }
+
+        let covered = fs::read_to_string(&path).ok().and_then(|s| parse_rect(&s));
+
+        if covered.is_some_and(|covered| overlaps(covered, rect)) {
+            remove_entry(&path.with_extension("body"))?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    /// An empty cache directory that is unique to the test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("darkmap-cache-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn rect(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Rect {
+        Rect::new(coord! { x: min_x, y: min_y }, coord! { x: max_x, y: max_y })
+    }
+
+    fn set_age(dir: &Path, query: &str, secs: u64) {
+        let (_, body_path) = paths(dir, query);
+        File::options()
+            .write(true)
+            .open(body_path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn keys_are_stable() {
+        assert_eq!(key(""), "cbf29ce484222325");
+        assert_eq!(key("a"), "af63dc4c8601ec8c");
+        assert_ne!(key("node(1);out;"), key("node(2);out;"));
+    }
+
+    #[test]
+    fn rect_round_trip() {
+        let area = rect(13.404954, 52.520008, 13.4105, 52.5237);
+        assert_eq!(parse_rect(&format_rect(area)), Some(area));
+        assert_eq!(parse_rect("1,2,3"), None);
+        assert_eq!(parse_rect("1,2,3,x"), None);
+    }
+
+    #[test]
+    fn overlapping_areas() {
+        let area = rect(0., 0., 1., 1.);
+        assert!(overlaps(area, rect(0.5, 0.5, 2., 2.)));
+        assert!(overlaps(area, rect(0.2, 0.2, 0.8, 0.8)));
+        // neighbours sharing an edge or a corner
+        assert!(!overlaps(area, rect(1., 0., 2., 1.)));
+        assert!(!overlaps(area, rect(1., 1., 2., 2.)));
+        assert!(!overlaps(area, rect(3., 3., 4., 4.)));
+    }
+
+    #[test]
+    fn reads_what_was_written() {
+        let dir = temp_dir("read");
+        let config = CacheConfig { dir: Some(dir.clone()), ..default() };
+
+        write(&config, "query", rect(0., 0., 1., 1.), "body").unwrap();
+
+        assert_eq!(read(&config, "query").as_deref(), Some("body"));
+        assert_eq!(read(&config, "other"), None);
+        assert_eq!(
+            parse_rect(&fs::read_to_string(dir.join(format!("{}.bbox", key("query")))).unwrap()),
+            Some(rect(0., 0., 1., 1.))
+        );
+
+        let disabled = CacheConfig {
+            dir: Some(dir.clone()),
+            enabled: false,
+            ..default()
+        };
+        assert_eq!(read(&disabled, "query"), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn expires_old_entries() {
+        let dir = temp_dir("expire");
+        let config = CacheConfig {
+            dir: Some(dir.clone()),
+            max_age: Some(60.),
+            ..default()
+        };
+
+        write(&config, "query", rect(0., 0., 1., 1.), "body").unwrap();
+        set_age(&dir, "query", 120);
+        assert_eq!(read(&config, "query"), None);
+
+        // but are still good enough when offline
+        let offline = CacheConfig {
+            dir: Some(dir.clone()),
+            max_age: Some(60.),
+            offline: true,
+            ..default()
+        };
+        assert_eq!(read(&offline, "query").as_deref(), Some("body"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn prunes_oldest_entries() {
+        let dir = temp_dir("prune");
+        let config = CacheConfig { dir: Some(dir.clone()), ..default() };
+
+        for (i, query) in ["a", "b", "c"].into_iter().enumerate() {
+            write(&config, query, rect(0., 0., 1., 1.), "0123456789").unwrap();
+            set_age(&dir, query, 100 - i as u64 * 10);
+        }
+
+        prune(&dir, 20).unwrap();
+
+        assert_eq!(read(&config, "a"), None);
+        assert!(!dir.join(format!("{}.query", key("a"))).exists());
+        assert!(!dir.join(format!("{}.bbox", key("a"))).exists());
+        assert!(read(&config, "b").is_some());
+        assert!(read(&config, "c").is_some());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalidates_overlapping_entries() {
+        let dir = temp_dir("invalidate");
+        let config = CacheConfig { dir: Some(dir.clone()), ..default() };
+
+        write(&config, "tile", rect(0., 0., 1., 1.), "body").unwrap();
+        write(&config, "neighbour", rect(1., 0., 2., 1.), "body").unwrap();
+        write(&config, "around", rect(-1., -1., 3., 3.), "body").unwrap();
+
+        assert_eq!(invalidate_in(&dir, rect(0., 0., 1., 1.)).unwrap(), 2);
+        assert_eq!(read(&config, "tile"), None);
+        assert_eq!(read(&config, "around"), None);
+        assert!(read(&config, "neighbour").is_some());
+
+        assert_eq!(invalidate_in(&dir.join("missing"), rect(0., 0., 1., 1.)).unwrap(), 0);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
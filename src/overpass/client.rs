@@ -0,0 +1,99 @@
+use std::{collections::HashMap, env, time::Duration};
+
+use anyhow::Context;
+use serde::Deserialize;
+use surf::http::headers::HeaderName;
+
+/// Connection settings for the Overpass API, read from the `[overpass]` section of the config
+/// file and the `DARKMAP_OVERPASS_*` environment variables.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    /// Interpreter URL that queries are posted to.
+    pub endpoint: String,
+    /// Per-request timeout in seconds, or no timeout if unset.
+    pub timeout: Option<f64>,
+    pub user_agent: String,
+    /// Extra headers sent with every request, e.g. for authenticating against a private instance.
+    pub headers: HashMap<String, String>,
+    /// Maximum number of queries running at the same time, lowered to the rate limit reported by
+    /// the server.
+    pub max_concurrent: usize,
+    /// How often a query is retried when the server is busy.
+    pub max_retries: u32,
+    /// Delay before the first retry in seconds, doubled on every further attempt.
+    pub backoff: f64,
+    /// Upper bound for the retry delay in seconds.
+    pub max_backoff: f64,
+    /// Load all layers of an area with a single query instead of one query per layer.
+    pub combine: bool,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "https://overpass-api.de/api/interpreter".to_string(),
+            timeout: Some(180.),
+            user_agent: concat!("darkmap/", env!("CARGO_PKG_VERSION")).to_string(),
+            headers: HashMap::new(),
+            max_concurrent: 2,
+            max_retries: 5,
+            backoff: 2.,
+            max_backoff: 60.,
+            combine: true,
+        }
+    }
+}
+
+impl ClientConfig {
+    pub(crate) fn apply_env(&mut self) -> anyhow::Result<()> {
+        if let Ok(endpoint) = env::var("DARKMAP_OVERPASS_ENDPOINT") {
+            self.endpoint = endpoint;
+        }
+
+        if let Ok(timeout) = env::var("DARKMAP_OVERPASS_TIMEOUT") {
+            self.timeout = match timeout.as_str() {
+                "" | "none" => None,
+                secs => Some(secs.parse().context("Invalid DARKMAP_OVERPASS_TIMEOUT")?),
+            };
+        }
+
+        if let Ok(user_agent) = env::var("DARKMAP_OVERPASS_USER_AGENT") {
+            self.user_agent = user_agent;
+        }
+
+        // semicolon-separated list of `Name: value` pairs
+        if let Ok(headers) = env::var("DARKMAP_OVERPASS_HEADERS") {
+            for header in headers.split(';').filter(|h| !h.trim().is_empty()) {
+                let (name, value) = header.split_once(':').with_context(|| {
+                    format!("Invalid header in DARKMAP_OVERPASS_HEADERS: {header}")
+                })?;
+
+                self.headers
+                    .insert(name.trim().to_string(), value.trim().to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn client(&self) -> anyhow::Result<surf::Client> {
+        let mut config = surf::Config::new()
+            .set_timeout(self.timeout.map(Duration::from_secs_f64))
+            .add_header("User-Agent", self.user_agent.as_str())
+            .map_err(|e| e.into_inner())?;
+
+        for (name, value) in &self.headers {
+            let name = name
+                .parse::<HeaderName>()
+                .map_err(|e| e.into_inner())
+                .with_context(|| format!("Invalid header name: {name}"))?;
+
+            config = config
+                .add_header(name, value.as_str())
+                .map_err(|e| e.into_inner())?;
+        }
+
+        Ok(config.try_into()?)
+    }
+}
@@ -0,0 +1,267 @@
+use format_serde_error::SerdeError;
+use itertools::Itertools;
+use surf::http::StatusCode;
+use thiserror::Error;
+
+use super::ApiResponse;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to download data (status: {status})")]
+    LoadError {
+        error: anyhow::Error,
+        status: surf::http::StatusCode,
+    },
+    #[error("Failed to parse data")]
+    ParseError(#[from] SerdeError),
+    #[error("Failed to parse XML data")]
+    XmlError(#[source] anyhow::Error),
+    #[error("Query is not cached and offline mode is enabled")]
+    Offline,
+    #[error("Rate limited by the Overpass server: {message}")]
+    RateLimited { message: String },
+    #[error("Overpass query timed out: {message}")]
+    Timeout { message: String },
+    #[error("Overpass server ran out of memory: {message}")]
+    OutOfMemory { message: String },
+    /// The query finished with a runtime error after part of the result was already written.
+    #[error("Overpass returned incomplete data: {message}")]
+    Remark { message: String, partial: Box<ApiResponse> },
+    #[error("Overpass returned an error page (status: {status}): {message}")]
+    HtmlError { status: StatusCode, message: String },
+}
+
+impl From<surf::Error> for Error {
+    fn from(value: surf::Error) -> Self {
+        Self::LoadError {
+            status: value.status(),
+            error: value.into_inner(),
+        }
+    }
+}
+
+impl Error {
+    /// Classifies a runtime error message, as found in a `remark` or on an HTML error page.
+    fn from_message(message: String) -> Option<Self> {
+        let lower = message.to_lowercase();
+
+        if lower.contains("timed out") || lower.contains("timeout") {
+            Some(Self::Timeout { message })
+        } else if lower.contains("out of memory") {
+            Some(Self::OutOfMemory { message })
+        } else if lower.contains("rate_limited") || lower.contains("too many requests") {
+            Some(Self::RateLimited { message })
+        } else {
+            None
+        }
+    }
+
+    /// Checks a response for the ways Overpass reports failure: the HTTP status, or an HTML error
+    /// page in place of the requested format.
+    pub(super) fn from_response(status: StatusCode, body: &str) -> Option<Self> {
+        let message = if is_html(body) {
+            html_message(body)
+        } else {
+            body.trim().to_string()
+        };
+
+        match status {
+            StatusCode::TooManyRequests => return Some(Self::RateLimited { message }),
+            StatusCode::GatewayTimeout => return Some(Self::Timeout { message }),
+            _ => {}
+        }
+
+        if is_html(body) {
+            return Some(
+                Self::from_message(message.clone()).unwrap_or(Self::HtmlError { status, message }),
+            );
+        }
+
+        if !status.is_success() {
+            return Some(Self::LoadError {
+                error: anyhow::anyhow!(message),
+                status,
+            });
+        }
+
+        None
+    }
+
+    /// Checks a parsed response for a `remark`, which Overpass uses to report runtime errors that
+    /// happened after it had already started sending the result. Errors are only classified if
+    /// nothing was returned, so a query that timed out halfway still keeps what it found.
+    pub(super) fn from_remark(res: ApiResponse) -> Result<ApiResponse, Self> {
+        let Some(message) = res.remark.clone() else {
+            return Ok(res);
+        };
+
+        if !res.elements.is_empty() {
+            return Err(Self::Remark { message, partial: Box::new(res) });
+        }
+
+        Err(Self::from_message(message.clone())
+            .unwrap_or(Self::Remark { message, partial: Box::new(res) }))
+    }
+}
+
+fn is_html(body: &str) -> bool {
+    let head = body.get(..512).unwrap_or(body).to_lowercase();
+
+    head.contains("<html") || head.contains("<!doctype html")
+}
+
+/// Extracts the error lines from an Overpass HTML error page, which look like
+/// `<p><strong style="color:#FF0000">Error</strong>: runtime error: ...</p>`.
+fn html_message(body: &str) -> String {
+    let mut text = String::with_capacity(body.len());
+    let mut in_tag = false;
+
+    for c in body.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    let text = text
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+
+    let errors = text
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("Error:"))
+        .map(str::trim)
+        .join("\n");
+
+    if errors.is_empty() {
+        text.split_whitespace().join(" ")
+    } else {
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::Point;
+
+    use super::*;
+    use crate::overpass::{Element, Node, Osm3s, Tags};
+
+    fn error_page(error: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Strict//EN"
+    "http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd">
+<html xmlns="http://www.w3.org/1999/xhtml" xml:lang="en" lang="en">
+<head>
+  <meta http-equiv="content-type" content="text/html; charset=utf-8" lang="en"/>
+  <title>OSM3S Response</title>
+</head>
+<body>
+
+<p>The data included in this document is from www.openstreetmap.org. The data is made available under ODbL.</p>
+<p><strong style="color:#FF0000">Error</strong>: {error} </p>
+
+</body>
+</html>
+"#
+        )
+    }
+
+    const RATE_LIMITED: &str = "runtime error: open64: 0 Success /osm3s_osm_base \
+        Dispatcher_Client::request_read_and_idx::rate_limited. Please check /api/status for the \
+        quota of your IP address.";
+
+    const TIMEOUT: &str = "runtime error: open64: 0 Success /osm3s_osm_base \
+        Dispatcher_Client::request_read_and_idx::timeout. The server is probably too busy to \
+        handle your request.";
+
+    const QUERY_TIMED_OUT: &str =
+        "runtime error: Query timed out in &quot;query&quot; at line 3 after 26 seconds.";
+
+    const REMARK_TIMED_OUT: &str =
+        "runtime error: Query timed out in \"recurse\" at line 1 after 25 seconds.";
+
+    const REMARK_OUT_OF_MEMORY: &str = "runtime error: Query ran out of memory in \"query\" at \
+        line 4. It would need at least 0 MB of RAM to continue.";
+
+    fn response(remark: &str, elements: Vec<Element>) -> ApiResponse {
+        ApiResponse {
+            version: 0.6,
+            generator: "Overpass API 0.7.61.5 4133829e".to_string(),
+            osm3s: Osm3s::default(),
+            bounds: None,
+            remark: Some(remark.to_string()),
+            elements,
+        }
+    }
+
+    fn node() -> Element {
+        Element::Node(Node {
+            id: 1,
+            point: Point::new(4.9, 52.4),
+            version: None,
+            tags: Tags::default(),
+        })
+    }
+
+    #[test]
+    fn extracts_html_error_message() {
+        assert_eq!(html_message(&error_page(RATE_LIMITED)), RATE_LIMITED);
+        assert_eq!(
+            html_message(&error_page(QUERY_TIMED_OUT)),
+            "runtime error: Query timed out in \"query\" at line 3 after 26 seconds."
+        );
+        assert_eq!(html_message("<html><body><h1>Bad Gateway</h1></body></html>"), "Bad Gateway");
+    }
+
+    #[test]
+    fn classifies_error_pages() {
+        let res = Error::from_response(StatusCode::TooManyRequests, &error_page(RATE_LIMITED));
+        assert!(matches!(res, Some(Error::RateLimited { message }) if message == RATE_LIMITED));
+
+        let res = Error::from_response(StatusCode::GatewayTimeout, &error_page(TIMEOUT));
+        assert!(matches!(res, Some(Error::Timeout { message }) if message == TIMEOUT));
+
+        // runtime errors are also reported with a successful status
+        let res = Error::from_response(StatusCode::Ok, &error_page(QUERY_TIMED_OUT));
+        assert!(matches!(res, Some(Error::Timeout { .. })));
+
+        let res = Error::from_response(StatusCode::Ok, &error_page("static error: unknown type"));
+        assert!(matches!(res, Some(Error::HtmlError { .. })));
+
+        assert!(Error::from_response(StatusCode::Ok, "{\"elements\": []}").is_none());
+    }
+
+    #[test]
+    fn classifies_messages() {
+        let message = |s: &str| Error::from_message(s.to_string());
+
+        assert!(matches!(message(RATE_LIMITED), Some(Error::RateLimited { .. })));
+        assert!(matches!(message(TIMEOUT), Some(Error::Timeout { .. })));
+        assert!(matches!(message(REMARK_TIMED_OUT), Some(Error::Timeout { .. })));
+        assert!(matches!(message(REMARK_OUT_OF_MEMORY), Some(Error::OutOfMemory { .. })));
+        assert!(message("runtime error: something else").is_none());
+    }
+
+    #[test]
+    fn keeps_partial_results() {
+        let res = Error::from_remark(response(REMARK_TIMED_OUT, vec![]));
+        assert!(matches!(res, Err(Error::Timeout { .. })));
+
+        let res = Error::from_remark(response(REMARK_TIMED_OUT, vec![node()]));
+        assert!(matches!(res, Err(Error::Remark { partial, .. }) if partial.elements.len() == 1));
+
+        let res = Error::from_remark(response("runtime error: something else", vec![]));
+        assert!(matches!(res, Err(Error::Remark { .. })));
+
+        let mut complete = response("", vec![node()]);
+        complete.remark = None;
+        assert!(matches!(Error::from_remark(complete), Ok(res) if res.elements.len() == 1));
+    }
+}
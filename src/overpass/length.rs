@@ -0,0 +1,115 @@
+use std::{fmt, str::FromStr};
+
+/// A distance given in a tag value like `height`, in metres.
+///
+/// Plain numbers are metres as usual in OSM, but values with units like `12 m`, `40 ft`, `40'` or
+/// `12'6"` are accepted too, as are decimal commas like `12,5`. Only the first of several values
+/// separated by semicolons is used.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Length(pub f32);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidLength(String);
+
+impl fmt::Display for InvalidLength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid length: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidLength {}
+
+impl FromStr for Length {
+    type Err = InvalidLength;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidLength(s.to_string());
+
+        let value = s.split(';').next().unwrap_or_default().trim();
+        let mut rest = value;
+        let mut metres = 0.;
+        let mut parts = 0;
+
+        while !rest.is_empty() {
+            // a number, with a decimal point or comma
+            let end = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ','))
+                .unwrap_or(rest.len());
+            let number = rest[..end].replace(',', ".");
+            let number = number.parse::<f32>().map_err(|_| invalid())?;
+            rest = rest[end..].trim_start();
+
+            // followed by its unit, which can only be left out for a single value in metres
+            let end = rest
+                .find(|c: char| c.is_ascii_digit() || c.is_whitespace())
+                .unwrap_or(rest.len());
+            let factor = match &rest[..end] {
+                "" if parts == 0 => 1.,
+                "m" | "meter" | "meters" | "metre" | "metres" => 1.,
+                "cm" => 0.01,
+                "km" => 1000.,
+                "ft" | "foot" | "feet" | "'" | "′" => 0.3048,
+                "in" | "inch" | "inches" | "\"" | "″" => 0.0254,
+                _ => return Err(invalid()),
+            };
+            rest = rest[end..].trim_start();
+
+            metres += number * factor;
+            parts += 1;
+        }
+
+        if parts == 0 || !metres.is_finite() {
+            return Err(invalid());
+        }
+
+        Ok(Self(metres))
+    }
+}
+
+/// Number of storeys in a tag value like `building:levels`, which may be fractional for half
+/// storeys.
+pub fn parse_levels(s: &str) -> Option<f32> {
+    let value = s.split(';').next()?.trim().replace(',', ".");
+    value
+        .parse()
+        .ok()
+        .filter(|levels: &f32| levels.is_finite() && *levels >= 0.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metres(s: &str) -> f32 {
+        s.parse::<Length>().unwrap().0
+    }
+
+    #[test]
+    fn parses_lengths() {
+        assert_eq!(metres("12"), 12.);
+        assert_eq!(metres("12m"), 12.);
+        assert_eq!(metres("12 m"), 12.);
+        assert!((metres("40'") - 12.192).abs() < 1e-4);
+        assert!((metres("40 ft") - 12.192).abs() < 1e-4);
+        assert!((metres("12'6\"") - 3.81).abs() < 1e-4);
+        assert_eq!(metres("12,5"), 12.5);
+        assert_eq!(metres("5;7"), 5.);
+    }
+
+    #[test]
+    fn rejects_invalid_lengths() {
+        assert!("tall".parse::<Length>().is_err());
+        assert!("-3".parse::<Length>().is_err());
+        assert!("".parse::<Length>().is_err());
+    }
+
+    #[test]
+    fn parses_levels() {
+        assert_eq!(parse_levels("3"), Some(3.));
+        assert_eq!(parse_levels("2.5"), Some(2.5));
+        assert_eq!(parse_levels("2,5"), Some(2.5));
+        assert_eq!(parse_levels("3;4"), Some(3.));
+        assert_eq!(parse_levels("-1"), None);
+        assert_eq!(parse_levels("tall"), None);
+    }
+}
@@ -0,0 +1,52 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use futures::future::{BoxFuture, FutureExt, Shared};
+
+type Read<T> = Shared<BoxFuture<'static, Result<Arc<T>, Arc<String>>>>;
+
+/// Contents of local map files, read once on a blocking thread and then shared by every load from
+/// the same path.
+pub struct FileCache<T>(Mutex<HashMap<PathBuf, Read<T>>>);
+
+impl<T> Default for FileCache<T> {
+    fn default() -> Self {
+        Self(Mutex::default())
+    }
+}
+
+impl<T: Send + Sync + 'static> FileCache<T> {
+    /// Returns the contents of `path` as produced by `read`, reading it only if no other load has
+    /// done so yet. Failed reads are retried by the next load.
+    pub async fn get(
+        &self,
+        path: &Path,
+        read: fn(&Path) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Arc<T>> {
+        let shared = {
+            let mut files = self.0.lock().unwrap();
+            if files
+                .get(path)
+                .is_some_and(|shared| matches!(shared.peek(), Some(Err(_))))
+            {
+                files.remove(path);
+            }
+
+            files
+                .entry(path.to_owned())
+                .or_insert_with(|| {
+                    let path = path.to_owned();
+                    blocking::unblock(move || read(&path))
+                        .map(|res| res.map(Arc::new).map_err(|e| Arc::new(format!("{e:#}"))))
+                        .boxed()
+                        .shared()
+                })
+                .clone()
+        };
+
+        shared.await.map_err(|e| anyhow::anyhow!("{e}"))
+    }
+}
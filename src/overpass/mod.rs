@@ -1,63 +1,138 @@
 #![allow(dead_code)]
 
-use std::{collections::HashMap, ops::Deref};
+pub mod cache;
+mod client;
+mod error;
+mod length;
+mod local;
+pub mod pbf;
+mod scheduler;
+pub mod xml;
+
+use std::{collections::HashMap, hash::Hash, ops::Deref};
 
 use bevy::prelude::*;
 use format_serde_error::SerdeError;
-use geo::{Coord, LineString, MultiPolygon, Point, Polygon, Rect};
+use geo::{Area, Contains, Coord, LineString, MultiPolygon, Point, Polygon, Rect};
 use lazy_static::lazy_static;
 use serde::Deserialize;
-use thiserror::Error;
 
-lazy_static! {
-    static ref CLIENT: surf::Client = surf::Client::new();
-}
+pub use self::{
+    client::ClientConfig,
+    error::Error,
+    length::{parse_levels, Length},
+};
+
+/// Height of one storey, for telling the height of a building from its number of levels.
+pub const LEVEL_HEIGHT: f32 = 3.;
 
-#[derive(Error, Debug)]
-pub enum Error {
-    #[error("Failed to download data (status: {status})")]
-    LoadError {
-        error: anyhow::Error,
-        status: surf::http::StatusCode,
-    },
-    #[error("Failed to parse data")]
-    ParseError(#[from] SerdeError),
+lazy_static! {
+    static ref CLIENT: surf::Client = crate::config::get()
+        .overpass
+        .client()
+        .expect("Failed to create Overpass client");
 }
 
-impl From<surf::Error> for Error {
-    fn from(value: surf::Error) -> Self {
-        Self::LoadError {
-            status: value.status(),
-            error: value.into_inner(),
-        }
+/// Runs a query covering `rect`, or answers it from the cache.
+pub async fn load(query: &str, rect: Rect) -> Result<ApiResponse, Error> {
+    let (body, cached) = match cache::get(query).await {
+        Some(body) => (body, true),
+        None if cache::is_offline() => return Err(Error::Offline),
+        None => (scheduler::run(query).await?, false),
+    };
+
+    let res = if xml::is_xml(&body) {
+        xml::parse(&body).map_err(Error::XmlError)?
+    } else {
+        format_serde_error::set_default_context_lines(20);
+        serde_json::from_str(&body).map_err(|e| SerdeError::new(body.clone(), e))?
+    };
+
+    let res = Error::from_remark(res)?;
+
+    if !cached {
+        cache::put(query, rect, &body).await;
     }
+
+    Ok(res)
 }
 
-pub async fn load(query: &str) -> Result<ApiResponse, Error> {
+async fn download(query: &str) -> Result<String, Error> {
     let body = format!("data={}", urlencoding::encode(query));
 
     let mut res = CLIENT
-        .post("https://overpass-api.de/api/interpreter")
+        .post(&crate::config::get().overpass.endpoint)
         .header("Content-Type", "application/x-www-form-urlencoded")
-        .header("Accept", "application/json")
+        .header("Accept", "application/json, application/osm3s+xml")
         .body(body)
         .await?;
 
     let body = res.body_string().await?;
 
-    format_serde_error::set_default_context_lines(20);
-    Ok(serde_json::from_str(&body).map_err(|e| SerdeError::new(body, e))?)
+    if let Some(error) = Error::from_response(res.status(), &body) {
+        return Err(error);
+    }
+
+    Ok(body)
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct ApiResponse {
     pub version: f64,
     pub generator: String,
     pub osm3s: Osm3s,
+    #[serde(default, with = "opt_rect")]
+    pub bounds: Option<Rect>,
+    #[serde(default)]
+    pub remark: Option<String>,
     pub elements: Vec<Element>,
 }
 
-#[derive(Debug, Deserialize)]
+impl ApiResponse {
+    /// Splits the response up by the selectors of each key, for a query that loaded several layers
+    /// at once. Elements matching several keys end up in each of them.
+    pub fn partition<K: Copy + Eq + Hash>(
+        self,
+        keys: &[(K, &[Selector])],
+    ) -> HashMap<K, ApiResponse> {
+        let mut parts = keys
+            .iter()
+            .map(|&(key, _)| {
+                (key, ApiResponse {
+                    version: self.version,
+                    generator: self.generator.clone(),
+                    osm3s: self.osm3s.clone(),
+                    bounds: self.bounds,
+                    remark: self.remark.clone(),
+                    elements: vec![],
+                })
+            })
+            .collect::<HashMap<_, _>>();
+
+        for elem in self.elements {
+            let mut matching = keys
+                .iter()
+                .filter(|(_, selectors)| Selector::any(selectors, elem.kind(), elem.tags()))
+                .map(|&(key, _)| key)
+                .peekable();
+
+            while let Some(key) = matching.next() {
+                let elements = &mut parts.get_mut(&key).unwrap().elements;
+
+                if matching.peek().is_some() {
+                    elements.push(elem.clone());
+                } else {
+                    elements.push(elem);
+                    break;
+                }
+            }
+        }
+
+        parts
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
 pub struct Osm3s {
     pub timestamp_osm_base: Option<String>,
     pub timestamp_areas_base: Option<String>,
@@ -67,7 +142,7 @@ pub struct Osm3s {
     pub copyright: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 #[serde(tag = "type")]
 pub enum Element {
     #[serde(rename = "node")]
@@ -78,16 +153,107 @@ pub enum Element {
     Relation(Relation),
 }
 
-#[derive(Debug, Deserialize)]
+impl Element {
+    pub fn kind(&self) -> ElementKind {
+        match self {
+            Element::Node(_) => ElementKind::Node,
+            Element::Way(_) => ElementKind::Way,
+            Element::Relation(_) => ElementKind::Relation,
+        }
+    }
+
+    pub fn tags(&self) -> &Tags {
+        match self {
+            Element::Node(node) => &node.tags,
+            Element::Way(way) => &way.tags,
+            Element::Relation(rel) => &rel.tags,
+        }
+    }
+
+    pub fn osm_id(&self) -> OsmId {
+        let (id, version) = match self {
+            Element::Node(node) => (node.id, node.version),
+            Element::Way(way) => (way.id, way.version),
+            Element::Relation(rel) => (rel.id, rel.version),
+        };
+
+        OsmId { kind: self.kind(), id, version }
+    }
+}
+
+/// Identifies the OSM element an entity was created from, so the same element loaded by
+/// overlapping requests is only spawned once.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct OsmId {
+    pub kind: ElementKind,
+    pub id: i64,
+    pub version: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ElementKind {
+    Node,
+    Way,
+    Relation,
+}
+
+/// Local equivalent of an Overpass filter like `way[building]`, used to select elements from
+/// sources that can't run the `.ovp` queries themselves.
+#[derive(Clone, Copy, Debug)]
+pub struct Selector {
+    pub kind: ElementKind,
+    pub key: &'static str,
+}
+
+impl Selector {
+    pub const fn node(key: &'static str) -> Self {
+        Self { kind: ElementKind::Node, key }
+    }
+
+    pub const fn way(key: &'static str) -> Self {
+        Self { kind: ElementKind::Way, key }
+    }
+
+    pub const fn relation(key: &'static str) -> Self {
+        Self { kind: ElementKind::Relation, key }
+    }
+
+    pub fn matches(&self, kind: ElementKind, tags: &Tags) -> bool {
+        self.kind == kind && tags.contains_key(self.key)
+    }
+
+    pub fn any(selectors: &[Selector], kind: ElementKind, tags: &Tags) -> bool {
+        selectors.iter().any(|s| s.matches(kind, tags))
+    }
+
+    /// The Overpass statement for the same filter within `bbox`, like
+    /// `way["building"](52.5,13.4,52.6,13.5);`.
+    pub fn statement(&self, bbox: &str) -> String {
+        let kind = match self.kind {
+            ElementKind::Node => "node",
+            ElementKind::Way => "way",
+            ElementKind::Relation => "relation",
+        };
+
+        format!("{kind}[\"{}\"]({bbox});", self.key)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct Node {
+    #[serde(alias = "ref")]
     pub id: i64,
     #[serde(flatten)]
     #[serde(with = "point")]
     pub point: Point,
+    /// Only included with `out meta`.
+    #[serde(default)]
+    pub version: Option<u32>,
+    #[serde(default)]
     pub tags: Tags,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct Way {
     #[serde(alias = "ref")]
     pub id: i64,
@@ -97,6 +263,9 @@ pub struct Way {
     pub nodes: Option<Vec<i64>>,
     #[serde(with = "vec_coord")]
     pub geometry: Vec<Coord>,
+    /// Only included with `out meta`.
+    #[serde(default)]
+    pub version: Option<u32>,
     #[serde(default)]
     pub tags: Tags,
 }
@@ -169,61 +338,112 @@ impl Bounds {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct Relation {
+    #[serde(alias = "ref")]
     pub id: i64,
     #[serde(default, with = "opt_rect")]
     pub bounds: Option<Rect>,
-    pub members: Vec<Element>,
+    #[serde(default)]
+    pub members: Vec<Member>,
+    /// Only included with `out meta`.
+    #[serde(default)]
+    pub version: Option<u32>,
+    #[serde(default)]
     pub tags: Tags,
 }
 
 impl Relation {
+    /// Assembles the member ways into polygons following the multipolygon rules: ways are joined
+    /// end to end in either direction until they form closed rings, and every inner ring becomes
+    /// a hole in the smallest outer ring that contains it. Members without a role are treated as
+    /// outer ways. Returns `None` if no closed outer ring could be formed.
     pub fn polygon(&self) -> Option<MultiPolygon> {
-        let mut outers: Vec<LineString> = vec![];
-
-        for member in &self.members {
-            if let Element::Way(way) = member {
-                if let Some(multiline) = way.multiline() {
-                    if let Some(cont) = outers
-                        .iter_mut()
-                        .find(|o| o.coords().last() == multiline.coords().next())
-                    {
-                        let coords = cont
-                            .coords()
-                            .chain(multiline.coords().skip(1))
-                            .copied()
-                            .collect();
-
-                        *cont = LineString::new(coords);
-                    } else {
-                        outers.push(multiline)
-                    }
+        let ways = |roles: &[&str]| {
+            self.members
+                .iter()
+                .filter(|m| roles.contains(&m.role.as_str()))
+                .filter_map(|m| match &m.element {
+                    Element::Way(way) => Some(way.geometry.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut polygons = assemble_rings(ways(&["outer", ""]))
+            .into_iter()
+            .map(|ring| Polygon::new(ring, vec![]))
+            .collect::<Vec<_>>();
+
+        if polygons.is_empty() {
+            return None;
+        }
+
+        // smallest first, so the first outer ring containing an inner ring is the tightest fit
+        polygons.sort_by(|a, b| a.unsigned_area().total_cmp(&b.unsigned_area()));
+
+        for inner in assemble_rings(ways(&["inner"])) {
+            // only the outline counts, as an outer ring may lie inside the hole of another
+            let inner = Polygon::new(inner, vec![]);
+            let outer = polygons
+                .iter_mut()
+                .find(|p| Polygon::new(p.exterior().clone(), vec![]).contains(&inner));
+
+            match outer {
+                Some(outer) => outer.interiors_push(inner.into_inner().0),
+                None => {
+                    warn!("Relation {} has an inner ring outside all of its outer rings", self.id)
                 }
             }
         }
 
-        let _outers: Vec<Polygon> = outers
-            .into_iter()
-            .filter(|ls| ls.is_closed())
-            .map(|ls| Polygon::new(ls, vec![]))
-            .collect();
+        Some(MultiPolygon::new(polygons))
+    }
+}
+
+/// Joins way segments into closed rings, reversing segments where needed. Segments that can't
+/// be closed into a ring are dropped.
+fn assemble_rings(mut segments: Vec<Vec<Coord>>) -> Vec<LineString> {
+    let mut rings = vec![];
+
+    segments.retain(|s| s.len() > 1);
 
-        todo!()
+    while let Some(mut ring) = segments.pop() {
+        while ring.first() != ring.last() {
+            let end = ring[ring.len() - 1];
+
+            let Some(index) = segments
+                .iter()
+                .position(|s| s.first() == Some(&end) || s.last() == Some(&end))
+            else {
+                break;
+            };
+
+            let mut next = segments.swap_remove(index);
+            if next.first() != Some(&end) {
+                next.reverse();
+            }
+
+            ring.extend(next.into_iter().skip(1));
+        }
+
+        if ring.len() >= 4 && ring.first() == ring.last() {
+            rings.push(LineString::new(ring));
+        }
     }
+
+    rings
 }
 
-// #[derive(Debug, Deserialize)]
-// pub enum Member {
-//     #[serde(rename = "node")]
-//     Node(NodeMember),
-//     #[serde(rename = "way")]
-//     Way(WayMember),
-//     #[serde(rename = "relation")]
-//     Relation(RelationMember),
-// }
-
-#[derive(Component, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Member {
+    #[serde(default)]
+    pub role: String,
+    #[serde(flatten)]
+    pub element: Element,
+}
+
+#[derive(Component, Clone, Debug, Default, Deserialize)]
 #[serde(transparent)]
 pub struct Tags(pub HashMap<String, String>);
 
@@ -236,16 +456,49 @@ impl Deref for Tags {
 }
 
 impl Tags {
-    pub fn building_height(&self) -> Option<f32> {
-        if let Some(height) = self.0.get("height") {
-            height.split_whitespace().next().unwrap().parse().ok()
-        } else if let Some(levels) = self.0.get("building:levels") {
-            levels.parse::<f32>().ok().map(|l| l * 3.)
-        } else {
-            None
+    /// Parses a tag holding a [`Length`] into metres, if it's there and valid.
+    pub fn length(&self, key: &str) -> Option<f32> {
+        let value = self.0.get(key)?;
+
+        match value.parse::<Length>() {
+            Ok(Length(metres)) => Some(metres),
+            Err(e) => {
+                debug!("Ignoring {}: {}", key, e);
+                None
+            }
         }
     }
 
+    /// Parses a tag holding a number of levels into metres.
+    fn levels(&self, key: &str) -> Option<f32> {
+        let levels = self.0.get(key).and_then(|levels| parse_levels(levels))?;
+        Some(levels * LEVEL_HEIGHT)
+    }
+
+    /// Tagged height of a building from the ground to the top of its roof.
+    pub fn height(&self) -> Option<f32> {
+        self.length("height").or_else(|| self.length("est_height"))
+    }
+
+    /// Height of a building from the ground to the top of its roof, or only to the top of its
+    /// walls if just the number of levels is known, since roof levels are counted separately.
+    pub fn building_height(&self) -> Option<f32> {
+        self.height().or_else(|| self.levels("building:levels"))
+    }
+
+    /// Height of the bottom of a building part above the ground, for parts that float above
+    /// others, like a bridge between two towers.
+    pub fn min_height(&self) -> Option<f32> {
+        self.length("min_height")
+            .or_else(|| self.levels("building:min_level"))
+    }
+
+    /// Height of a roof from the top of the walls.
+    pub fn roof_height(&self) -> Option<f32> {
+        self.length("roof:height")
+            .or_else(|| self.levels("roof:levels"))
+    }
+
     pub fn road_width(&self) -> f32 {
         // if let Some(width) = self
         //     .0
@@ -381,3 +634,107 @@ mod opt_rect {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn way(coords: &[(f64, f64)]) -> Element {
+        Element::Way(Way {
+            id: 0,
+            bounds: None,
+            nodes: None,
+            geometry: coords.iter().map(|&(x, y)| Coord { x, y }).collect(),
+            version: None,
+            tags: Tags::default(),
+        })
+    }
+
+    fn relation(members: Vec<(&str, Element)>) -> Relation {
+        Relation {
+            id: 1,
+            bounds: None,
+            members: members
+                .into_iter()
+                .map(|(role, element)| Member { role: role.to_string(), element })
+                .collect(),
+            version: None,
+            tags: Tags::default(),
+        }
+    }
+
+    #[test]
+    fn selector_statements() {
+        assert_eq!(Selector::node("name").statement("1,2,3,4"), r#"node["name"](1,2,3,4);"#);
+        assert_eq!(
+            Selector::relation("building:part").statement("1,2,3,4"),
+            r#"relation["building:part"](1,2,3,4);"#
+        );
+    }
+
+    #[test]
+    fn joins_split_outer_ways() {
+        let rel = relation(vec![
+            ("outer", way(&[(0., 0.), (1., 0.), (1., 1.)])),
+            ("outer", way(&[(1., 1.), (0., 1.), (0., 0.)])),
+        ]);
+
+        let polygon = rel.polygon().unwrap();
+        assert_eq!(polygon.0.len(), 1);
+        assert_eq!(polygon.0[0].exterior().0.len(), 5);
+        assert_eq!(polygon.unsigned_area(), 1.);
+    }
+
+    #[test]
+    fn reverses_segments() {
+        let rel = relation(vec![
+            ("outer", way(&[(0., 0.), (2., 0.)])),
+            ("outer", way(&[(2., 2.), (2., 0.)])),
+            ("", way(&[(0., 0.), (0., 2.)])),
+            ("outer", way(&[(2., 2.), (0., 2.)])),
+        ]);
+
+        let polygon = rel.polygon().unwrap();
+        assert_eq!(polygon.0.len(), 1);
+        assert_eq!(polygon.unsigned_area(), 4.);
+    }
+
+    #[test]
+    fn drops_unclosed_rings() {
+        let rel = relation(vec![("outer", way(&[(0., 0.), (1., 0.), (1., 1.)]))]);
+
+        assert!(rel.polygon().is_none());
+    }
+
+    #[test]
+    fn assigns_inner_rings_to_their_outer_ring() {
+        let rel = relation(vec![
+            ("outer", way(&[(0., 0.), (10., 0.), (10., 10.), (0., 10.), (0., 0.)])),
+            ("outer", way(&[(20., 0.), (30., 0.), (30., 10.), (20., 10.), (20., 0.)])),
+            // inside a hole of the first ring, so it belongs to this smaller ring
+            ("outer", way(&[(3., 3.), (7., 3.), (7., 7.), (3., 7.), (3., 3.)])),
+            ("inner", way(&[(1., 1.), (9., 1.), (9., 9.)])),
+            ("inner", way(&[(9., 9.), (1., 9.), (1., 1.)])),
+            ("inner", way(&[(4., 4.), (6., 4.), (6., 6.), (4., 6.), (4., 4.)])),
+            ("inner", way(&[(22., 2.), (24., 2.), (24., 4.), (22., 4.), (22., 2.)])),
+            // outside both outer rings
+            ("inner", way(&[(40., 0.), (41., 0.), (41., 1.), (40., 1.), (40., 0.)])),
+        ]);
+
+        let polygon = rel.polygon().unwrap();
+        assert_eq!(polygon.0.len(), 3);
+
+        let holes = |x, y| {
+            let outer = polygon
+                .0
+                .iter()
+                .find(|p| p.contains(&Point::new(x, y)))
+                .unwrap();
+            outer.interiors().len()
+        };
+        assert_eq!(holes(0.5, 0.5), 1);
+        assert_eq!(holes(3.5, 3.5), 1);
+        assert_eq!(holes(21., 1.), 1);
+        assert_eq!(polygon.unsigned_area(), 100. - 64. + 16. - 4. + 100. - 4.);
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use geo::{BoundingRect, Coord, Intersects, LineString, MultiLineString, Point, Rect};
+use lazy_static::lazy_static;
+use osmpbf::{Element as PbfElement, ElementReader, RelMemberType};
+
+use super::{
+    local::FileCache, ApiResponse, Element, ElementKind, Member, Node, Osm3s, Relation, Selector,
+    Tags, Way,
+};
+
+lazy_static! {
+    static ref FILES: FileCache<Index> = FileCache::default();
+}
+
+struct PendingWay {
+    id: i64,
+    version: Option<u32>,
+    refs: Vec<i64>,
+    tags: Tags,
+}
+
+struct PendingRelation {
+    id: i64,
+    version: Option<u32>,
+    ways: Vec<(String, i64)>,
+    tags: Tags,
+    bounds: Option<Rect>,
+}
+
+/// Extracts often leave out metadata, in which case the version is missing or negative.
+fn version(version: Option<i32>) -> Option<u32> {
+    version
+        .and_then(|v| u32::try_from(v).ok())
+        .filter(|v| *v > 0)
+}
+
+fn tags<'a>(iter: impl Iterator<Item = (&'a str, &'a str)>) -> Tags {
+    Tags(iter.map(|(k, v)| (k.to_string(), v.to_string())).collect())
+}
+
+/// Reads all elements within `bbox` that match any of the `selectors` from an `.osm.pbf` extract,
+/// producing the same elements an `out geom` Overpass query would.
+///
+/// The extract is only decoded by the first load from it, after which every layer and tile is
+/// answered from the same in-memory index.
+pub async fn load(path: &Path, bbox: Rect, selectors: &[Selector]) -> anyhow::Result<ApiResponse> {
+    let index = FILES.get(path, Index::read).await?;
+
+    Ok(index.query(bbox, selectors))
+}
+
+/// The tagged elements of an extract with their geometry resolved, and any untagged ways that
+/// are part of a relation.
+struct Index {
+    nodes: Vec<Node>,
+    ways: HashMap<i64, Way>,
+    relations: Vec<PendingRelation>,
+}
+
+impl Index {
+    /// Nodes are stored before ways and ways before relations in PBF files, so the file is read
+    /// three times: once for relations, once for the ways they need and all tagged ways, and once
+    /// for the coordinates of the nodes those ways reference.
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let mut relations = vec![];
+        let mut member_ways = HashSet::new();
+
+        ElementReader::from_path(path)?.for_each(|elem| {
+            if let PbfElement::Relation(rel) = elem {
+                let ways = rel
+                    .members()
+                    .filter(|m| m.member_type == RelMemberType::Way)
+                    .map(|m| (m.role().unwrap_or_default().to_string(), m.member_id))
+                    .collect::<Vec<_>>();
+
+                member_ways.extend(ways.iter().map(|(_, id)| *id));
+                relations.push(PendingRelation {
+                    id: rel.id(),
+                    version: version(rel.info().version()),
+                    ways,
+                    tags: tags(rel.tags()),
+                    bounds: None,
+                });
+            }
+        })?;
+
+        let mut pending_ways = vec![];
+        let mut needed_nodes = HashSet::new();
+
+        ElementReader::from_path(path)?.for_each(|elem| {
+            if let PbfElement::Way(way) = elem {
+                let tags = tags(way.tags());
+                if tags.0.is_empty() && !member_ways.contains(&way.id()) {
+                    return;
+                }
+
+                let refs = way.refs().collect::<Vec<_>>();
+                needed_nodes.extend(refs.iter().copied());
+                pending_ways.push(PendingWay {
+                    id: way.id(),
+                    version: version(way.info().version()),
+                    refs,
+                    tags,
+                });
+            }
+        })?;
+
+        let mut coords = HashMap::new();
+        let mut nodes = vec![];
+
+        let mut add_node = |id: i64, version: Option<u32>, lon: f64, lat: f64, tags: Tags| {
+            if needed_nodes.contains(&id) {
+                coords.insert(id, Coord { x: lon, y: lat });
+            }
+
+            if !tags.0.is_empty() {
+                nodes.push(Node {
+                    id,
+                    point: Point::new(lon, lat),
+                    version,
+                    tags,
+                });
+            }
+        };
+
+        ElementReader::from_path(path)?.for_each(|elem| match elem {
+            PbfElement::Node(node) => {
+                let version = version(node.info().version());
+                add_node(node.id(), version, node.lon(), node.lat(), tags(node.tags()))
+            }
+            PbfElement::DenseNode(node) => {
+                let version = version(node.info().map(|info| info.version()));
+                add_node(node.id(), version, node.lon(), node.lat(), tags(node.tags()))
+            }
+            _ => {}
+        })?;
+
+        // nodes can be missing from clipped extracts, in which case the way is cut short
+        let ways = pending_ways
+            .into_iter()
+            .map(|way| {
+                let geometry = way
+                    .refs
+                    .iter()
+                    .filter_map(|id| coords.get(id).copied())
+                    .collect::<Vec<_>>();
+
+                (way.id, Way {
+                    id: way.id,
+                    bounds: LineString::new(geometry.clone()).bounding_rect(),
+                    nodes: Some(way.refs),
+                    geometry,
+                    version: way.version,
+                    tags: way.tags,
+                })
+            })
+            .collect::<HashMap<_, _>>();
+
+        for rel in &mut relations {
+            rel.bounds = rel
+                .ways
+                .iter()
+                .filter_map(|(_, id)| Some(LineString::new(ways.get(id)?.geometry.clone())))
+                .collect::<MultiLineString>()
+                .bounding_rect();
+        }
+
+        Ok(Self { nodes, ways, relations })
+    }
+
+    fn query(&self, bbox: Rect, selectors: &[Selector]) -> ApiResponse {
+        let mut elements = vec![];
+
+        for node in &self.nodes {
+            if bbox.intersects(&node.point)
+                && Selector::any(selectors, ElementKind::Node, &node.tags)
+            {
+                elements.push(Element::Node(node.clone()));
+            }
+        }
+
+        for way in self.ways.values() {
+            if way.bounds.is_some_and(|b| b.intersects(&bbox))
+                && Selector::any(selectors, ElementKind::Way, &way.tags)
+            {
+                elements.push(Element::Way(way.clone()));
+            }
+        }
+
+        for rel in &self.relations {
+            if !rel.bounds.is_some_and(|b| b.intersects(&bbox))
+                || !Selector::any(selectors, ElementKind::Relation, &rel.tags)
+            {
+                continue;
+            }
+
+            elements.push(Element::Relation(Relation {
+                id: rel.id,
+                bounds: rel.bounds,
+                version: rel.version,
+                members: rel
+                    .ways
+                    .iter()
+                    .filter_map(|(role, id)| {
+                        let way = self.ways.get(id)?.clone();
+                        Some(Member {
+                            role: role.clone(),
+                            element: Element::Way(way),
+                        })
+                    })
+                    .collect(),
+                tags: rel.tags.clone(),
+            }));
+        }
+
+        ApiResponse {
+            version: 0.6,
+            generator: format!("darkmap {}", env!("CARGO_PKG_VERSION")),
+            osm3s: Osm3s::default(),
+            bounds: Some(bbox),
+            remark: None,
+            elements,
+        }
+    }
+}
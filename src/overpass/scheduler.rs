@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use async_io::Timer;
+use async_lock::{OnceCell, Semaphore};
+use bevy::prelude::*;
+
+use super::{Error, CLIENT};
+
+/// Bounds the number of queries in flight, since public instances only give out a couple of slots
+/// per client and answer anything beyond that with a 429.
+static SLOTS: OnceCell<Semaphore> = OnceCell::new();
+
+/// Sized from the configured maximum, lowered to the rate limit the server reports on first use.
+async fn slots() -> &'static Semaphore {
+    SLOTS
+        .get_or_init(|| async {
+            let max = crate::config::get().overpass.max_concurrent.max(1);
+
+            match slot_status().await {
+                // a limit of 0 means the instance doesn't limit this client
+                Ok(SlotStatus { rate_limit: Some(limit @ 1..), .. }) => {
+                    Semaphore::new(max.min(limit as usize))
+                }
+                Ok(_) => Semaphore::new(max),
+                Err(e) => {
+                    warn!("Failed to get Overpass status: {}", e);
+                    Semaphore::new(max)
+                }
+            }
+        })
+        .await
+}
+
+impl Error {
+    /// Whether the request might succeed if it's tried again later.
+    fn is_retryable(&self) -> bool {
+        match self {
+            Error::RateLimited { .. } | Error::Timeout { .. } | Error::OutOfMemory { .. } => true,
+            Error::LoadError { status, .. } => status.is_server_error(),
+            _ => false,
+        }
+    }
+}
+
+/// Runs a query once a slot is free, retrying with exponential backoff when the server is busy.
+/// After a 429 the server's `/api/status` is checked to wait exactly until the next slot frees up.
+pub(super) async fn run(query: &str) -> Result<String, Error> {
+    let config = &crate::config::get().overpass;
+    let mut attempt = 0;
+
+    loop {
+        let res = {
+            let _slot = slots().await.acquire().await;
+            super::download(query).await
+        };
+
+        let error = match res {
+            Err(error) if error.is_retryable() && attempt < config.max_retries => error,
+            res => return res,
+        };
+
+        let backoff = Duration::from_secs_f64(
+            (config.backoff * 2f64.powi(attempt as i32)).min(config.max_backoff),
+        );
+
+        let wait = match error {
+            Error::RateLimited { .. } => match slot_status().await {
+                Ok(status) => status.next_slot().unwrap_or(backoff),
+                Err(e) => {
+                    warn!("Failed to get Overpass status: {}", e);
+                    backoff
+                }
+            },
+            _ => backoff,
+        };
+
+        attempt += 1;
+        warn!("{}, retrying in {:.1?} (attempt {}/{})", error, wait, attempt, config.max_retries);
+
+        Timer::after(wait).await;
+    }
+}
+
+/// Slot information as reported by `/api/status`.
+#[derive(Debug, Default)]
+struct SlotStatus {
+    rate_limit: Option<u32>,
+    available_now: u32,
+    available_in: Vec<Duration>,
+}
+
+impl SlotStatus {
+    /// Parses the plain text status page, which contains lines like:
+    ///
+    /// ```text
+    /// Rate limit: 2
+    /// 1 slots available now.
+    /// Slot available after: 2024-01-30T12:00:00Z, in 12 seconds.
+    /// ```
+    fn parse(text: &str) -> Self {
+        let mut status = Self::default();
+
+        for line in text.lines().map(str::trim) {
+            if let Some(limit) = line.strip_prefix("Rate limit:") {
+                status.rate_limit = limit.trim().parse().ok();
+            } else if let Some(count) = line.strip_suffix("slots available now.") {
+                status.available_now = count.trim().parse().unwrap_or(0);
+            } else if line.starts_with("Slot available after:") {
+                if let Some(secs) = line
+                    .rsplit_once(" in ")
+                    .and_then(|(_, rest)| rest.split_whitespace().next())
+                    .and_then(|secs| secs.parse::<u64>().ok())
+                {
+                    status.available_in.push(Duration::from_secs(secs));
+                }
+            }
+        }
+
+        status
+    }
+
+    /// How long until a query can be started, if the status says anything about it.
+    fn next_slot(&self) -> Option<Duration> {
+        if self.available_now > 0 {
+            Some(Duration::ZERO)
+        } else {
+            self.available_in.iter().min().copied()
+        }
+    }
+}
+
+/// The status page lives next to the interpreter, e.g. `https://overpass-api.de/api/status`.
+async fn slot_status() -> Result<SlotStatus, Error> {
+    let endpoint = &crate::config::get().overpass.endpoint;
+    let url = match endpoint.rsplit_once('/') {
+        Some((base, _)) => format!("{base}/status"),
+        None => format!("{endpoint}/status"),
+    };
+
+    let text = CLIENT.get(url).recv_string().await?;
+
+    Ok(SlotStatus::parse(&text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_available_slots() {
+        let status = SlotStatus::parse(
+            "Connected as: 3232235777
+Current time: 2024-01-30T11:59:48Z
+Announced endpoint: lambert.openstreetmap.de/
+Rate limit: 2
+2 slots available now.
+Currently running queries (pid, space limit, time limit, start time):
+",
+        );
+
+        assert_eq!(status.rate_limit, Some(2));
+        assert_eq!(status.available_now, 2);
+        assert!(status.available_in.is_empty());
+        assert_eq!(status.next_slot(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn parses_slots_available_later() {
+        let status = SlotStatus::parse(
+            "Connected as: 3232235777
+Current time: 2024-01-30T11:59:48Z
+Announced endpoint: lambert.openstreetmap.de/
+Rate limit: 2
+Slot available after: 2024-01-30T12:00:40Z, in 52 seconds.
+Slot available after: 2024-01-30T12:00:05Z, in 17 seconds.
+Currently running queries (pid, space limit, time limit, start time):
+13931\t536870912\t180\t2024-01-30T11:59:40Z
+",
+        );
+
+        assert_eq!(status.rate_limit, Some(2));
+        assert_eq!(status.available_now, 0);
+        assert_eq!(status.available_in, vec![Duration::from_secs(52), Duration::from_secs(17)]);
+        assert_eq!(status.next_slot(), Some(Duration::from_secs(17)));
+    }
+
+    #[test]
+    fn parses_unlimited_status() {
+        let status = SlotStatus::parse("Rate limit: 0\nCurrently running queries:\n");
+
+        assert_eq!(status.rate_limit, Some(0));
+        assert_eq!(status.next_slot(), None);
+    }
+}
@@ -0,0 +1,501 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{bail, Context};
+use geo::{BoundingRect, Coord, Intersects, LineString, Point, Rect};
+use lazy_static::lazy_static;
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+use super::{
+    local::FileCache, ApiResponse, Element, Member, Node, Osm3s, Relation, Selector, Tags, Way,
+};
+
+lazy_static! {
+    static ref FILES: FileCache<ApiResponse> = FileCache::default();
+}
+
+/// Returns true if the body looks like OSM XML rather than JSON. HTML error pages also start
+/// with an XML declaration, so this looks for the `<osm>` root element.
+pub fn is_xml(body: &str) -> bool {
+    let body = body.trim_start();
+    let head = body.get(..512).unwrap_or(body);
+
+    body.starts_with("<osm") || (body.starts_with("<?xml") && head.contains("<osm"))
+}
+
+/// Reads all elements within `bbox` that match any of the `selectors` from an `.osm` file. The
+/// file is only parsed by the first load from it and kept in memory for the ones after.
+pub async fn load(path: &Path, bbox: Rect, selectors: &[Selector]) -> anyhow::Result<ApiResponse> {
+    let file = FILES
+        .get(path, |path| parse(&fs::read_to_string(path)?))
+        .await?;
+
+    let elements = file
+        .elements
+        .iter()
+        .filter(|elem| {
+            let in_bbox = match elem {
+                Element::Node(node) => bbox.intersects(&node.point),
+                Element::Way(Way { bounds, .. }) | Element::Relation(Relation { bounds, .. }) => {
+                    bounds.is_some_and(|b| b.intersects(&bbox))
+                }
+            };
+
+            in_bbox && Selector::any(selectors, elem.kind(), elem.tags())
+        })
+        .cloned()
+        .collect();
+
+    Ok(ApiResponse {
+        version: file.version,
+        generator: file.generator.clone(),
+        osm3s: file.osm3s.clone(),
+        bounds: file.bounds,
+        remark: file.remark.clone(),
+        elements,
+    })
+}
+
+struct Attrs(HashMap<String, String>);
+
+impl Attrs {
+    fn new(e: &BytesStart) -> anyhow::Result<Self> {
+        let mut attrs = HashMap::new();
+
+        for attr in e.attributes() {
+            let attr = attr?;
+            attrs.insert(
+                String::from_utf8_lossy(attr.key.as_ref()).into_owned(),
+                attr.unescape_value()?.into_owned(),
+            );
+        }
+
+        Ok(Self(attrs))
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|s| s.as_str())
+    }
+
+    fn parse<T: std::str::FromStr>(&self, key: &str) -> anyhow::Result<T>
+    where
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        self.get(key)
+            .with_context(|| format!("Missing attribute `{key}`"))?
+            .parse()
+            .with_context(|| format!("Invalid attribute `{key}`"))
+    }
+
+    fn parse_opt<T: std::str::FromStr>(&self, key: &str) -> anyhow::Result<Option<T>>
+    where
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        self.get(key).map(|_| self.parse(key)).transpose()
+    }
+
+    fn coord(&self) -> anyhow::Result<Option<Coord>> {
+        if self.get("lat").is_none() && self.get("lon").is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Coord {
+            x: self.parse("lon")?,
+            y: self.parse("lat")?,
+        }))
+    }
+
+    fn rect(&self) -> anyhow::Result<Rect> {
+        Ok(Rect::new(
+            (self.parse::<f64>("minlon")?, self.parse::<f64>("minlat")?),
+            (self.parse::<f64>("maxlon")?, self.parse::<f64>("maxlat")?),
+        ))
+    }
+}
+
+/// A relation member whose geometry may still have to be looked up by reference.
+enum PendingMember {
+    Node { id: i64, coord: Option<Coord> },
+    Way { id: i64, geometry: Vec<Coord> },
+    Relation { id: i64 },
+}
+
+enum Current {
+    None,
+    Node(Node),
+    Way(Way, Vec<i64>),
+    Relation(Relation, Vec<(String, PendingMember)>),
+}
+
+/// Parses an OSM XML document, either a `[out:xml]` Overpass response or a file exported from
+/// JOSM or the OSM API, into the same structure as the JSON format.
+///
+/// Way and relation member geometry is taken from inline coordinates when present (`out geom`)
+/// and otherwise resolved from the nodes and ways elsewhere in the document.
+pub fn parse(body: &str) -> anyhow::Result<ApiResponse> {
+    let mut reader = Reader::from_str(body);
+    reader.trim_text(true);
+
+    let mut res = ApiResponse {
+        version: 0.6,
+        generator: String::new(),
+        osm3s: Osm3s::default(),
+        bounds: None,
+        remark: None,
+        elements: vec![],
+    };
+
+    let mut current = Current::None;
+    let mut pending_members = vec![];
+    let mut in_remark = false;
+
+    loop {
+        let event = reader
+            .read_event()
+            .with_context(|| format!("XML error at position {}", reader.buffer_position()))?;
+
+        let (e, empty) = match &event {
+            Event::Start(e) => (e, false),
+            Event::Empty(e) => (e, true),
+            Event::End(e) => {
+                match e.name().as_ref() {
+                    b"node" | b"way" | b"relation" => {
+                        finish(&mut current, &mut res.elements, &mut pending_members)
+                    }
+                    b"remark" => in_remark = false,
+                    _ => {}
+                }
+                continue;
+            }
+            Event::Text(text) => {
+                if in_remark {
+                    res.remark = Some(text.unescape()?.trim().to_string());
+                }
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        let attrs = Attrs::new(e)?;
+
+        match (e.name().as_ref(), &mut current) {
+            (b"osm", _) => {
+                if let Some(version) = attrs.get("version") {
+                    res.version = version.parse().context("Invalid OSM version")?;
+                }
+                if let Some(generator) = attrs.get("generator") {
+                    res.generator = generator.to_string();
+                }
+            }
+            (b"meta", _) => {
+                res.osm3s.timestamp_osm_base = attrs.get("osm_base").map(str::to_string);
+                res.osm3s.timestamp_areas_base = attrs.get("areas").map(str::to_string);
+            }
+            (b"remark", _) => in_remark = !empty,
+            (b"node", Current::None) => {
+                let coord = attrs.coord()?.context("Node without coordinates")?;
+                current = Current::Node(Node {
+                    id: attrs.parse("id")?,
+                    point: coord.into(),
+                    version: attrs.parse_opt("version")?,
+                    tags: Tags::default(),
+                });
+            }
+            (b"way", Current::None) => {
+                let way = Way {
+                    id: attrs.parse("id")?,
+                    bounds: None,
+                    nodes: Some(vec![]),
+                    geometry: vec![],
+                    version: attrs.parse_opt("version")?,
+                    tags: Tags::default(),
+                };
+                current = Current::Way(way, vec![]);
+            }
+            (b"relation", Current::None) => {
+                let rel = Relation {
+                    id: attrs.parse("id")?,
+                    bounds: None,
+                    members: vec![],
+                    version: attrs.parse_opt("version")?,
+                    tags: Tags::default(),
+                };
+                current = Current::Relation(rel, vec![]);
+            }
+            (b"bounds", Current::None) => res.bounds = Some(attrs.rect()?),
+            (b"bounds", Current::Way(way, _)) => way.bounds = Some(attrs.rect()?),
+            (b"bounds", Current::Relation(rel, _)) => rel.bounds = Some(attrs.rect()?),
+            (b"tag", Current::Node(Node { tags, .. }))
+            | (b"tag", Current::Way(Way { tags, .. }, _))
+            | (b"tag", Current::Relation(Relation { tags, .. }, _)) => {
+                tags.0.insert(attrs.parse("k")?, attrs.parse("v")?);
+            }
+            (b"nd", Current::Way(way, missing)) => {
+                let id = attrs.parse("ref")?;
+                way.nodes.get_or_insert_with(Vec::new).push(id);
+
+                match attrs.coord()? {
+                    Some(coord) => way.geometry.push(coord),
+                    None => missing.push(id),
+                }
+            }
+            // inline geometry of the last way member
+            (b"nd", Current::Relation(_, members)) => {
+                if let (Some((_, PendingMember::Way { geometry, .. })), Some(coord)) =
+                    (members.last_mut(), attrs.coord()?)
+                {
+                    geometry.push(coord);
+                }
+            }
+            (b"member", Current::Relation(_, members)) => {
+                let id = attrs.parse("ref")?;
+                let role = attrs.get("role").unwrap_or_default().to_string();
+                members.push((role, match attrs.get("type") {
+                    Some("node") => PendingMember::Node { id, coord: attrs.coord()? },
+                    Some("way") => PendingMember::Way { id, geometry: vec![] },
+                    Some("relation") => PendingMember::Relation { id },
+                    other => bail!("Unknown member type {:?}", other),
+                }));
+            }
+            _ => {}
+        }
+
+        if empty {
+            if let b"node" | b"way" | b"relation" = e.name().as_ref() {
+                finish(&mut current, &mut res.elements, &mut pending_members);
+            }
+        }
+    }
+
+    resolve(&mut res.elements, pending_members);
+
+    Ok(res)
+}
+
+/// Moves the element being parsed into `elements`. Relations are kept aside with their members
+/// until the whole document has been read.
+fn finish(
+    current: &mut Current,
+    elements: &mut Vec<Element>,
+    pending_members: &mut Vec<(usize, Vec<(String, PendingMember)>)>,
+) {
+    match std::mem::replace(current, Current::None) {
+        Current::None => {}
+        Current::Node(node) => elements.push(Element::Node(node)),
+        Current::Way(mut way, missing) => {
+            // geometry is filled in later if any of the nodes had no inline coordinates
+            if !missing.is_empty() {
+                way.geometry.clear();
+            }
+            elements.push(Element::Way(way));
+        }
+        Current::Relation(rel, members) => {
+            pending_members.push((elements.len(), members));
+            elements.push(Element::Relation(rel));
+        }
+    }
+}
+
+/// Fills in way geometry from node references and relation members from the elements they
+/// refer to, and computes bounds where the document didn't include them.
+fn resolve(elements: &mut [Element], pending_members: Vec<(usize, Vec<(String, PendingMember)>)>) {
+    let coords = elements
+        .iter()
+        .filter_map(|elem| match elem {
+            Element::Node(node) => Some((node.id, node.point.0)),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+
+    for elem in elements.iter_mut() {
+        if let Element::Way(way) = elem {
+            if way.geometry.is_empty() {
+                if let Some(nodes) = &way.nodes {
+                    way.geometry = nodes
+                        .iter()
+                        .filter_map(|id| coords.get(id).copied())
+                        .collect();
+                }
+            }
+
+            if way.bounds.is_none() {
+                way.bounds = LineString::new(way.geometry.clone()).bounding_rect();
+            }
+        }
+    }
+
+    let way_geometry = elements
+        .iter()
+        .filter_map(|elem| match elem {
+            Element::Way(way) => Some((way.id, way.geometry.clone())),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+
+    for (index, members) in pending_members {
+        let Element::Relation(rel) = &mut elements[index] else {
+            continue;
+        };
+
+        rel.members = members
+            .into_iter()
+            .filter_map(|(role, member)| {
+                let element = match member {
+                    PendingMember::Node { id, coord } => {
+                        let coord = coord.or_else(|| coords.get(&id).copied())?;
+                        Some(Element::Node(Node {
+                            id,
+                            point: Point(coord),
+                            version: None,
+                            tags: Tags::default(),
+                        }))
+                    }
+                    PendingMember::Way { id, mut geometry } => {
+                        if geometry.is_empty() {
+                            geometry = way_geometry.get(&id)?.clone();
+                        }
+
+                        Some(Element::Way(Way {
+                            id,
+                            bounds: LineString::new(geometry.clone()).bounding_rect(),
+                            nodes: None,
+                            geometry,
+                            version: None,
+                            tags: Tags::default(),
+                        }))
+                    }
+                    PendingMember::Relation { id } => Some(Element::Relation(Relation {
+                        id,
+                        bounds: None,
+                        members: vec![],
+                        version: None,
+                        tags: Tags::default(),
+                    })),
+                }?;
+
+                Some(Member { role, element })
+            })
+            .collect();
+
+        if rel.bounds.is_none() {
+            rel.bounds = rel
+                .members
+                .iter()
+                .filter_map(|member| match &member.element {
+                    Element::Way(way) => Some(LineString::new(way.geometry.clone())),
+                    _ => None,
+                })
+                .collect::<geo::MultiLineString>()
+                .bounding_rect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6" generator="JOSM">
+  <bounds minlat="52.0" minlon="4.0" maxlat="52.1" maxlon="4.1"/>
+  <node id="1" lat="52.00" lon="4.00" version="2"/>
+  <node id="2" lat="52.00" lon="4.01"/>
+  <node id="3" lat="52.01" lon="4.01"/>
+  <node id="4" lat="52.05" lon="4.05">
+    <tag k="amenity" v="cafe"/>
+    <tag k="name" v="Caf&#233; &amp; Bar"/>
+  </node>
+  <way id="10" version="3">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <nd ref="1"/>
+    <tag k="building" v="yes"/>
+  </way>
+  <relation id="20">
+    <member type="way" ref="10" role="outer"/>
+    <member type="node" ref="4" role="label"/>
+    <member type="way" ref="99" role="inner"/>
+    <tag k="type" v="multipolygon"/>
+  </relation>
+</osm>
+"#;
+
+    #[test]
+    fn detects_osm_xml() {
+        assert!(is_xml(FIXTURE));
+        assert!(is_xml("<osm version=\"0.6\"></osm>"));
+        assert!(!is_xml("{\"version\": 0.6, \"elements\": []}"));
+        assert!(!is_xml("<?xml version=\"1.0\"?>\n<html><body>Error</body></html>"));
+    }
+
+    #[test]
+    fn parses_elements_and_members() {
+        let res = parse(FIXTURE).unwrap();
+
+        assert_eq!(res.generator, "JOSM");
+        assert_eq!(res.bounds, Some(Rect::new((4.0, 52.0), (4.1, 52.1))));
+        assert_eq!(res.elements.len(), 6);
+
+        let Element::Node(cafe) = &res.elements[3] else {
+            panic!("expected a node")
+        };
+        assert_eq!(cafe.id, 4);
+        assert_eq!(cafe.point, Point::new(4.05, 52.05));
+        assert_eq!(cafe.tags.get("name").map(String::as_str), Some("Café & Bar"));
+
+        let Element::Way(way) = &res.elements[4] else {
+            panic!("expected a way")
+        };
+        assert_eq!(way.version, Some(3));
+        assert_eq!(way.nodes, Some(vec![1, 2, 3, 1]));
+        assert_eq!(way.geometry.len(), 4);
+        assert_eq!(way.geometry[1], Coord { x: 4.01, y: 52.00 });
+        assert_eq!(way.bounds, Some(Rect::new((4.0, 52.0), (4.01, 52.01))));
+
+        // the member referring to a way that isn't in the file is left out
+        let Element::Relation(rel) = &res.elements[5] else {
+            panic!("expected a relation")
+        };
+        assert_eq!(rel.members.len(), 2);
+        assert_eq!(rel.members[0].role, "outer");
+        assert!(matches!(&rel.members[0].element, Element::Way(w) if w.geometry == way.geometry));
+        assert_eq!(rel.members[1].role, "label");
+        assert!(matches!(&rel.members[1].element, Element::Node(n) if n.point == cafe.point));
+        assert_eq!(rel.bounds, way.bounds);
+    }
+
+    #[test]
+    fn uses_inline_geometry() {
+        let res = parse(
+            r#"<osm version="0.6" generator="Overpass API">
+              <way id="10">
+                <bounds minlat="52.0" minlon="4.0" maxlat="52.01" maxlon="4.01"/>
+                <nd ref="1" lat="52.00" lon="4.00"/>
+                <nd ref="2" lat="52.01" lon="4.01"/>
+                <tag k="highway" v="footway"/>
+              </way>
+              <relation id="20">
+                <member type="way" ref="10" role="outer">
+                  <nd lat="52.00" lon="4.00"/>
+                  <nd lat="52.01" lon="4.01"/>
+                </member>
+              </relation>
+            </osm>"#,
+        )
+        .unwrap();
+
+        let Element::Way(way) = &res.elements[0] else {
+            panic!("expected a way")
+        };
+        assert_eq!(way.geometry, vec![Coord { x: 4.00, y: 52.00 }, Coord { x: 4.01, y: 52.01 }]);
+
+        let Element::Relation(rel) = &res.elements[1] else {
+            panic!("expected a relation")
+        };
+        assert!(matches!(&rel.members[0].element, Element::Way(w) if w.geometry == way.geometry));
+    }
+}
@@ -1,15 +1,17 @@
+use std::time::Instant;
+
 use anyhow::Context;
 use bevy::prelude::*;
 use bevy_mod_outline::{OutlineBundle, OutlineMeshExt, OutlineVolume};
 use bevy_mod_picking::prelude::*;
 use geo::Contains;
-use serde_json::json;
 
 use crate::{
     buildings::Building,
     common::{DecorateRequest, WorldPosition},
-    loading::{LoadRequest, LoadType, LoadingPlugin},
-    overpass::{Element, Tags},
+    decorate::{Decorate, DecorateQueue, DecorateSet},
+    loading::{fetch, LoadRequest, LoadType, LoadingPlugin},
+    overpass::{Element, OsmId, Selector, Tags},
     ui::label::Label,
     viewport::view_distance::ViewDistance,
     SUBWAY_DEPTH,
@@ -21,7 +23,7 @@ pub struct PoiPlugin;
 impl Plugin for PoiPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(LoadingPlugin::<PointOfInterest>::new())
-            .add_systems(Update, (decorate_poi, move_up));
+            .add_systems(Update, (decorate_poi.in_set(DecorateSet), move_up));
     }
 }
 
@@ -29,25 +31,22 @@ impl Plugin for PoiPlugin {
 pub struct PointOfInterest;
 
 impl LoadType for PointOfInterest {
-    type Bundle = impl Bundle;
-
-    async fn load(req: LoadRequest) -> anyhow::Result<Vec<Self::Bundle>> {
-        let template = include_str!("../../assets/queries/poi.ovp");
+    const QUERY: &'static str = include_str!("../../assets/queries/poi.ovp");
+    const SELECTORS: &'static [Selector] = &[Selector::node("name")];
 
-        let query = handlebars::Handlebars::new()
-            .render_template(template, &json!({ "bbox": req.bbox() }))
-            .context("Failed to render query")?;
+    type Bundle = impl Bundle;
 
-        let res = crate::overpass::load(&query)
-            .await
-            .context("Failed to load POI")?;
+    async fn load(req: LoadRequest) -> anyhow::Result<Vec<(OsmId, Self::Bundle)>> {
+        let res = fetch::<Self>(&req).await.context("Failed to load POI")?;
 
         Ok(res
             .elements
             .into_iter()
             .flat_map(|elem| {
+                let id = elem.osm_id();
+
                 if let Element::Node(node) = elem {
-                    Some((Self, node.tags, WorldPosition(node.point), DecorateRequest))
+                    Some((id, (Self, node.tags, WorldPosition(node.point), DecorateRequest)))
                 } else {
                     None
                 }
@@ -57,22 +56,29 @@ impl LoadType for PointOfInterest {
 }
 
 fn decorate_poi(
-    mut query: Query<
-        (Entity, &Tags, &mut Transform),
-        (With<DecorateRequest>, With<PointOfInterest>),
-    >,
+    mut query: Query<(Entity, &Tags, &mut Transform), (With<Decorate>, With<PointOfInterest>)>,
     assets: Res<AssetServer>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
+    mut queue: ResMut<DecorateQueue>,
     mut commands: Commands,
 ) {
     if query.iter().next().is_none() {
         return;
     }
 
+    let start = Instant::now();
+    let mut count = 0;
+
     let font = assets.load("fonts/NotoSansJP-Regular.ttf");
 
-    for (entity, tags, mut transform) in query.iter_mut().take(1000) {
+    for (entity, tags, mut transform) in query.iter_mut() {
+        if count > 0 && !queue.has_time(start.elapsed()) {
+            break;
+        }
+
+        count += 1;
+
         let font = font.clone();
 
         let mut mesh = Mesh::from(shape::UVSphere { radius: 1., sectors: 4, stacks: 2 });
@@ -83,7 +89,7 @@ fn decorate_poi(
         }
 
         let mut cmds = commands.entity(entity);
-        cmds.remove::<DecorateRequest>().insert((
+        cmds.remove::<Decorate>().insert((
             meshes.add(mesh),
             materials.add(StandardMaterial {
                 base_color: Color::rgb(1., 0., 0.),
@@ -124,6 +130,8 @@ fn decorate_poi(
             cmds.insert(Name::new(name.to_string()));
         }
     }
+
+    queue.record(start.elapsed(), count);
 }
 
 fn move_up(
@@ -137,6 +145,8 @@ fn move_up(
     >,
     mut commands: Commands,
 ) {
+    let config = &crate::config::get().buildings;
+
     for (poi_ent, poi_pos, mut poi_transform) in &mut pois {
         if poi_transform.translation.y != 0. {
             continue;
@@ -145,7 +155,7 @@ fn move_up(
         for (building_ent, building_transform, building, tags) in &buildings {
             if building.geometry.contains(&poi_pos.0) {
                 poi_transform.translation -= building_transform.translation;
-                poi_transform.translation.y = tags.building_height().unwrap_or(10.);
+                poi_transform.translation.y = building.height(tags, config);
 
                 commands
                     .entity(poi_ent)
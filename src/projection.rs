@@ -0,0 +1,110 @@
+use geo::{Coord, Point};
+
+/// Mean radius of the earth in metres, as used by the haversine functions in `geo`.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Spherical transverse Mercator projection centred on a coordinate, mapping longitude and
+/// latitude to metres east and north of it. Distances are exact along the central meridian and
+/// stretched by a factor of `1 + x² / 2R²` at `x` metres from it, about 1.2 millionths at 10 km,
+/// which is plenty for a city-sized map.
+#[derive(Clone, Copy, Debug)]
+pub struct Projection {
+    /// Central meridian, in radians.
+    lon: f64,
+    /// Latitude of the centre, in radians.
+    lat: f64,
+}
+
+impl Projection {
+    pub fn new(center: Point) -> Self {
+        Self {
+            lon: center.x().to_radians(),
+            lat: center.y().to_radians(),
+        }
+    }
+
+    /// Metres east (`x`) and north (`y`) of the centre.
+    pub fn project(&self, point: Point) -> Coord {
+        let lon = point.x().to_radians() - self.lon;
+        let lat = point.y().to_radians();
+
+        let b = lat.cos() * lon.sin();
+
+        Coord {
+            x: EARTH_RADIUS * b.atanh(),
+            y: EARTH_RADIUS * (lat.tan().atan2(lon.cos()) - self.lat),
+        }
+    }
+
+    /// Inverse of [`Self::project`].
+    pub fn unproject(&self, coord: Coord) -> Point {
+        let x = coord.x / EARTH_RADIUS;
+        let d = coord.y / EARTH_RADIUS + self.lat;
+
+        let lat = (d.sin() / x.cosh()).asin();
+        let lon = self.lon + x.sinh().atan2(d.cos());
+
+        Point::new(lon.to_degrees(), lat.to_degrees())
+    }
+
+    /// Position of `point` relative to the projected `origin`, narrowed to f32 only after taking
+    /// the difference. Like mesh coordinates, `x` points east and `y` along the world's z axis,
+    /// which is south.
+    pub fn local(&self, origin: Coord, point: Point) -> Coord<f32> {
+        let coord = self.project(point) - origin;
+
+        Coord {
+            x: coord.x as f32,
+            y: -coord.y as f32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let projection = Projection::new(Point::new(139.7714, 35.6997));
+
+        for point in [
+            Point::new(139.7714, 35.6997),
+            Point::new(139.8, 35.75),
+            Point::new(139.65, 35.6),
+            Point::new(-0.1276, 51.5072),
+        ] {
+            let back = projection.unproject(projection.project(point));
+            assert!((back.x() - point.x()).abs() < 1e-9, "{point:?} became {back:?}");
+            assert!((back.y() - point.y()).abs() < 1e-9, "{point:?} became {back:?}");
+        }
+    }
+
+    #[test]
+    fn degree_of_latitude() {
+        let projection = Projection::new(Point::new(4.9, 52.37));
+        let north = projection.project(Point::new(4.9, 53.37));
+
+        assert!(north.x.abs() < 1e-6);
+        assert!((north.y - 111_195.).abs() < 1., "{} m", north.y);
+    }
+
+    #[test]
+    fn east_and_north_are_positive() {
+        let center = Point::new(4.9, 52.37);
+        let projection = Projection::new(center);
+
+        let origin = projection.project(center);
+        assert!(origin.x.abs() < 1e-9 && origin.y.abs() < 1e-9);
+
+        let north_east = projection.project(Point::new(4.91, 52.38));
+        assert!(north_east.x > 0. && north_east.y > 0.);
+
+        let south_west = projection.project(Point::new(4.89, 52.36));
+        assert!(south_west.x < 0. && south_west.y < 0.);
+
+        // world z points south
+        let local = projection.local(origin, Point::new(4.91, 52.38));
+        assert!(local.x > 0. && local.y < 0.);
+    }
+}
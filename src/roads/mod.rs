@@ -1,4 +1,4 @@
-use std::f32::consts::FRAC_PI_2;
+use std::{f32::consts::FRAC_PI_2, time::Instant};
 
 use anyhow::Context;
 use bevy::{
@@ -6,15 +6,17 @@ use bevy::{
     prelude::*,
     render::{mesh::Indices, render_resource::PrimitiveTopology},
 };
-use geo::{Centroid, CoordsIter, HaversineBearing, HaversineDistance, LineString};
+use geo::{Centroid, Coord, CoordsIter, LineString};
 use itertools::Itertools;
-use serde_json::json;
 
 use crate::{
     color,
-    common::{DecorateRequest, WorldPosition},
-    loading::{LoadRequest, LoadType, LoadingPlugin},
-    overpass::{Element, Tags},
+    common::{insert_meshes, DecorateRequest, MeshTask, WorldPosition},
+    decorate::{Decorate, DecorateQueue, DecorateSet},
+    loading::{fetch, LoadRequest, LoadType, LoadingPlugin},
+    overpass::{Element, OsmId, Selector, Tags},
+    projection::Projection,
+    viewport::OriginCoordinate,
     COLORS, SUBWAY_DEPTH,
 };
 
@@ -24,7 +26,7 @@ pub struct RoadsPlugin;
 impl Plugin for RoadsPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(LoadingPlugin::<Road>::new())
-            .add_systems(Update, decorate_road);
+            .add_systems(Update, (decorate_road.in_set(DecorateSet), insert_meshes::<With<Road>>));
     }
 }
 
@@ -34,29 +36,29 @@ pub struct Road {
 }
 
 impl LoadType for Road {
-    type Bundle = impl Bundle;
-
-    async fn load(req: LoadRequest) -> anyhow::Result<Vec<Self::Bundle>> {
-        let template = include_str!("../../assets/queries/roads.ovp");
+    const QUERY: &'static str = include_str!("../../assets/queries/roads.ovp");
+    const SELECTORS: &'static [Selector] = &[Selector::way("highway"), Selector::way("railway")];
 
-        let query = handlebars::Handlebars::new()
-            .render_template(template, &json!({ "bbox": req.bbox() }))
-            .context("Failed to render query")?;
+    type Bundle = impl Bundle;
 
-        let res = crate::overpass::load(&query)
-            .await
-            .context("Failed to load roads")?;
+    async fn load(req: LoadRequest) -> anyhow::Result<Vec<(OsmId, Self::Bundle)>> {
+        let res = fetch::<Self>(&req).await.context("Failed to load roads")?;
 
         Ok(res
             .elements
             .into_iter()
             .flat_map(|elem| {
+                let id = elem.osm_id();
+
                 if let Element::Way(way) = elem {
                     Some((
-                        Self { geometry: way.geometry.into() },
-                        way.tags,
-                        WorldPosition(way.bounds.unwrap().centroid()),
-                        DecorateRequest,
+                        id,
+                        (
+                            Self { geometry: way.geometry.into() },
+                            way.tags,
+                            WorldPosition(way.bounds.unwrap().centroid()),
+                            DecorateRequest,
+                        ),
                     ))
                 } else {
                     None
@@ -67,15 +69,22 @@ impl LoadType for Road {
 }
 
 fn decorate_road(
-    query: Query<(Entity, &Road, &Tags, &WorldPosition), With<DecorateRequest>>,
+    query: Query<(Entity, &Road, &Tags, &WorldPosition), With<Decorate>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    mut meshes: ResMut<Assets<Mesh>>,
+    origin: Res<OriginCoordinate>,
+    mut queue: ResMut<DecorateQueue>,
     mut commands: Commands,
 ) {
-    for (entity, road, tags, pos) in query.iter().take(100) {
-        commands.entity(entity).remove::<DecorateRequest>();
+    let start = Instant::now();
+    let mut count = 0;
+
+    for (entity, road, tags, pos) in query.iter() {
+        if count > 0 && !queue.has_time(start.elapsed()) {
+            break;
+        }
 
-        let origin = pos.0;
+        commands.entity(entity).remove::<Decorate>();
+        count += 1;
 
         let layer = tags
             .0
@@ -131,92 +140,15 @@ fn decorate_road(
             + layer
             + bias;
 
-        // translate coords into meters
-        let geometry = road
-            .geometry
-            .coords_iter()
-            .map(|coord| {
-                let distance = origin.haversine_distance(&coord.into());
-                let bearing = origin.haversine_bearing(coord.into());
-
-                let ang = bearing.to_radians();
-
-                let x = (distance * ang.sin()) as f32;
-                let y = (distance * -ang.cos()) as f32;
-
-                Vec3::new(x, height, y)
-            })
-            .collect::<Vec<_>>();
-
         let half_width: f32 = tags.road_width() / 2.;
+        let area = tags.0.get("area").is_some();
 
-        let mesh = if tags.0.get("area").is_some() {
-            let vertices_2d = geometry.iter().flat_map(|v| [v.x, v.z]).collect::<Vec<_>>();
-
-            let indices = earcutr::earcut(&vertices_2d, &[], 2)
-                .expect("Failed to triangulate road")
-                .into_iter()
-                .map(|i| i as u32)
-                .array_chunks()
-                .flat_map(|[a, b, c]| [a, c, b])
-                .collect::<Vec<_>>();
-
-            let vertices = geometry.iter().map(|v| [v.x, v.y, v.z]).collect::<Vec<_>>();
-
-            let normals = (0..vertices.len())
-                .map(|_| Vec3::Y.into())
-                .collect::<Vec<[f32; 3]>>();
-
-            let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-            mesh.set_indices(Some(Indices::U32(indices)));
-            mesh
-        } else {
-            let mut vertices = Vec::<[f32; 3]>::new();
-
-            for (pos, (prev, this, next)) in geometry
-                .iter()
-                .take(1)
-                .chain(&geometry)
-                .chain(geometry.last())
-                .tuple_windows()
-                .with_position()
-            {
-                let angle = match pos {
-                    itertools::Position::First => (*next - *this).normalize(),
-                    itertools::Position::Middle => {
-                        let prev_angle = (*this - *prev).normalize();
-                        let next_angle = (*next - *this).normalize();
-                        (prev_angle + next_angle).normalize()
-                    }
-                    itertools::Position::Last => (*this - *prev).normalize(),
-                    itertools::Position::Only => break,
-                };
-
-                let left = Quat::from_rotation_y(FRAC_PI_2).mul_vec3(angle);
-                let right = Quat::from_rotation_y(-FRAC_PI_2).mul_vec3(angle);
-
-                vertices.push((*this + left * half_width).into());
-                vertices.push((*this + right * half_width).into());
-            }
-
-            let normals = (0..vertices.len())
-                .map(|_| Vec3::Y.into())
-                .collect::<Vec<[f32; 3]>>();
-
-            let indices = (0..vertices.len() as u32)
-                .tuple_windows()
-                .step_by(2)
-                .flat_map(|(a, b, c, d)| [a, b, c, c, b, d])
-                .collect::<Vec<_>>();
-
-            let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-            mesh.set_indices(Some(Indices::U32(indices)));
-            mesh
-        };
+        let geometry = road.geometry.clone();
+        let projection = origin.projection();
+        let center = projection.project(pos.0);
+        let mesh = MeshTask::spawn(move || {
+            road_mesh(&geometry, projection, center, height, half_width, area)
+        });
 
         let base_color = if let Some(color) = tags.0.get("colour").and_then(|s| Color::hex(s).ok())
         {
@@ -240,13 +172,107 @@ fn decorate_road(
         };
 
         let mut cmds = commands.entity(entity);
-        cmds.insert((
-            meshes.add(mesh),
-            materials.add(StandardMaterial { base_color, ..Default::default() }),
-        ));
+        cmds.insert((mesh, materials.add(StandardMaterial { base_color, ..Default::default() })));
 
         if let Some(name) = tags.name() {
             cmds.insert(Name::new(name.to_string()));
         }
     }
+
+    queue.record(start.elapsed(), count);
+}
+
+/// Flat strip along the road, or the filled outline for `area=yes`, around the projected `center`.
+fn road_mesh(
+    geometry: &LineString,
+    projection: Projection,
+    center: Coord,
+    height: f32,
+    half_width: f32,
+    area: bool,
+) -> Option<Mesh> {
+    // translate coords into meters
+    let geometry = geometry
+        .coords_iter()
+        .map(|coord| {
+            let local = projection.local(center, coord.into());
+
+            Vec3::new(local.x, height, local.y)
+        })
+        .collect::<Vec<_>>();
+
+    if area {
+        let vertices_2d = geometry.iter().flat_map(|v| [v.x, v.z]).collect::<Vec<_>>();
+
+        let indices = match earcutr::earcut(&vertices_2d, &[], 2) {
+            Ok(indices) => indices,
+            Err(e) => {
+                error!("Failed to triangulate road: {:?}", e);
+                return None;
+            }
+        };
+
+        let indices = indices
+            .into_iter()
+            .map(|i| i as u32)
+            .array_chunks()
+            .flat_map(|[a, b, c]| [a, c, b])
+            .collect::<Vec<_>>();
+
+        let vertices = geometry.iter().map(|v| [v.x, v.y, v.z]).collect::<Vec<_>>();
+
+        let normals = (0..vertices.len())
+            .map(|_| Vec3::Y.into())
+            .collect::<Vec<[f32; 3]>>();
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        Some(mesh)
+    } else {
+        let mut vertices = Vec::<[f32; 3]>::new();
+
+        for (pos, (prev, this, next)) in geometry
+            .iter()
+            .take(1)
+            .chain(&geometry)
+            .chain(geometry.last())
+            .tuple_windows()
+            .with_position()
+        {
+            let angle = match pos {
+                itertools::Position::First => (*next - *this).normalize(),
+                itertools::Position::Middle => {
+                    let prev_angle = (*this - *prev).normalize();
+                    let next_angle = (*next - *this).normalize();
+                    (prev_angle + next_angle).normalize()
+                }
+                itertools::Position::Last => (*this - *prev).normalize(),
+                itertools::Position::Only => break,
+            };
+
+            let left = Quat::from_rotation_y(FRAC_PI_2).mul_vec3(angle);
+            let right = Quat::from_rotation_y(-FRAC_PI_2).mul_vec3(angle);
+
+            vertices.push((*this + left * half_width).into());
+            vertices.push((*this + right * half_width).into());
+        }
+
+        let normals = (0..vertices.len())
+            .map(|_| Vec3::Y.into())
+            .collect::<Vec<[f32; 3]>>();
+
+        let indices = (0..vertices.len() as u32)
+            .tuple_windows()
+            .step_by(2)
+            .flat_map(|(a, b, c, d)| [a, b, c, c, b, d])
+            .collect::<Vec<_>>();
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        Some(mesh)
+    }
 }
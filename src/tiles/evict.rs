@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use bevy::{prelude::*, render::mesh::Indices};
+use bevy_panorbit_camera::PanOrbitCamera;
+use geo::HaversineDistance;
+
+use super::{
+    stream::{load_radius, wanted_tiles},
+    TileCoord, Tiles,
+};
+use crate::{
+    common::WorldPosition,
+    viewport::{MainCamera, OriginCoordinate},
+};
+
+/// Rough number of bytes a mesh takes up, in main memory and again on the GPU.
+fn mesh_size(mesh: &Mesh) -> u64 {
+    let vertices = mesh.count_vertices() * mesh.get_vertex_size() as usize;
+    let indices = match mesh.indices() {
+        Some(Indices::U16(indices)) => indices.len() * 2,
+        Some(Indices::U32(indices)) => indices.len() * 4,
+        None => 0,
+    };
+
+    (vertices + indices) as u64
+}
+
+/// Despawns tiles far away from the camera focus along with every feature positioned within
+/// them, and the furthest tiles outside the loaded area while over the memory budget. Meshes and
+/// materials are freed once the last handle to them is dropped with the entity, and the tiles are
+/// loaded again when they come back into range.
+///
+/// Features are grouped by the tile their [`WorldPosition`] falls in rather than the tile they
+/// were loaded with, since features crossing a tile edge are loaded by both tiles.
+pub(super) fn evict_tiles(
+    camera: Query<&PanOrbitCamera, With<MainCamera>>,
+    origin: Res<OriginCoordinate>,
+    tiles: Res<Tiles>,
+    features: Query<(Entity, &WorldPosition, Option<&Handle<Mesh>>), Without<Parent>>,
+    meshes: Res<Assets<Mesh>>,
+    mut commands: Commands,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+
+    let config = &crate::config::get().tiles;
+    let focus = origin.to_point(camera.focus);
+    let load_radius = load_radius(camera);
+    let evict_distance = config.evict_distance.max(load_radius * 1.5);
+    // tiles that would just be requested again
+    let wanted = wanted_tiles(focus, load_radius);
+
+    let mut areas = HashMap::<TileCoord, (Vec<Entity>, u64)>::new();
+    for (coord, _) in tiles.iter() {
+        areas.entry(coord).or_default();
+    }
+    for (entity, WorldPosition(point), mesh) in &features {
+        let (entities, size) = areas.entry(TileCoord::from_point(*point)).or_default();
+        entities.push(entity);
+        *size += mesh.and_then(|mesh| meshes.get(mesh)).map_or(0, mesh_size);
+    }
+
+    let mut areas = areas
+        .into_iter()
+        .map(|(coord, (entities, size))| {
+            (coord, focus.haversine_distance(&coord.center()), entities, size)
+        })
+        .collect::<Vec<_>>();
+
+    // furthest first
+    areas.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let mut total = areas.iter().map(|(.., size)| size).sum::<u64>();
+    let mut evicted = 0;
+
+    for (coord, distance, entities, size) in areas {
+        let over_budget = config.memory_budget.is_some_and(|budget| total > budget);
+        if wanted.contains(&coord) || (distance <= evict_distance && !over_budget) {
+            continue;
+        }
+
+        for entity in entities {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        if let Some(tile) = tiles.get(coord) {
+            commands.entity(tile).despawn_recursive();
+        }
+
+        total -= size;
+        evicted += 1;
+    }
+
+    if evicted > 0 {
+        debug!("Evicted {} tiles, about {} MiB of meshes left", evicted, total / 1024 / 1024);
+    }
+}
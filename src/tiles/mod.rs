@@ -0,0 +1,185 @@
+mod evict;
+mod stream;
+
+use std::{collections::HashMap, f64::consts::PI, time::Duration};
+
+use bevy::{prelude::*, time::common_conditions::on_timer};
+use geo::{Point, Rect};
+use serde::Deserialize;
+
+use crate::{
+    loading::{ActiveLoads, LoadRequest},
+    overpass,
+};
+
+/// Zoom level of the slippy map tiles the world is loaded in. At this level a tile is about a
+/// kilometre across at the equator and shrinks towards the poles.
+pub const TILE_ZOOM: u32 = 15;
+
+#[derive(Default)]
+pub struct TilesPlugin;
+
+impl Plugin for TilesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Tiles>()
+            .init_resource::<ActiveLoads>()
+            .add_event::<LoadTile>()
+            .add_event::<RefreshTile>()
+            .add_systems(
+                Update,
+                (
+                    (stream::request_tiles, load_tiles, stream::dispatch_tiles).chain(),
+                    refresh_tiles,
+                    forget_tiles,
+                    evict::evict_tiles.run_if(on_timer(Duration::from_secs(1))),
+                ),
+            );
+    }
+}
+
+/// Settings for loading and unloading tiles, read from the `[tiles]` section of the config file.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct TilesConfig {
+    /// Distance around the camera focus that is loaded, in metres.
+    pub load_radius: f64,
+    /// Tiles further than this from the camera focus are unloaded, in metres. Never less than
+    /// the distance tiles are loaded at.
+    pub evict_distance: f64,
+    /// Estimated size in bytes of the meshes of loaded features above which the furthest tiles
+    /// are unloaded, even if they're within `evict_distance`.
+    pub memory_budget: Option<u64>,
+}
+
+impl Default for TilesConfig {
+    fn default() -> Self {
+        Self {
+            load_radius: 1500.,
+            evict_distance: 5000.,
+            memory_budget: Some(1024 * 1024 * 1024),
+        }
+    }
+}
+
+/// Position of a tile in the slippy map grid at [`TILE_ZOOM`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileCoord {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileCoord {
+    fn size() -> f64 {
+        (1u32 << TILE_ZOOM) as f64
+    }
+
+    pub fn from_point(point: Point) -> Self {
+        let n = Self::size();
+        let lat = point.y().to_radians();
+
+        let x = (point.x() + 180.) / 360. * n;
+        let y = (1. - lat.tan().asinh() / PI) / 2. * n;
+
+        Self {
+            x: x.clamp(0., n - 1.) as u32,
+            y: y.clamp(0., n - 1.) as u32,
+        }
+    }
+
+    /// North-west corner of the tile at `(x, y)`, which may be one past the last tile.
+    fn corner(x: u32, y: u32) -> Point {
+        let n = Self::size();
+
+        let lon = x as f64 / n * 360. - 180.;
+        let lat = (PI * (1. - 2. * y as f64 / n)).sinh().atan().to_degrees();
+
+        Point::new(lon, lat)
+    }
+
+    pub fn rect(&self) -> Rect {
+        Rect::new(Self::corner(self.x, self.y), Self::corner(self.x + 1, self.y + 1))
+    }
+
+    pub fn center(&self) -> Point {
+        self.rect().center().into()
+    }
+
+    /// All tiles that overlap the given area.
+    pub fn covering(rect: Rect) -> impl Iterator<Item = TileCoord> {
+        let min = Self::from_point(Point::new(rect.min().x, rect.max().y));
+        let max = Self::from_point(Point::new(rect.max().x, rect.min().y));
+
+        (min.x..=max.x).flat_map(move |x| (min.y..=max.y).map(move |y| TileCoord { x, y }))
+    }
+}
+
+/// A loaded or loading area of the map. Each layer tracks its own progress on the tile entity in
+/// a [`LoadState`](crate::loading::LoadState).
+#[derive(Component, Debug)]
+pub struct Tile(pub TileCoord);
+
+/// Marks a tile that is waiting for its turn to load.
+#[derive(Component)]
+pub struct Queued;
+
+/// Tile entities by their position in the grid.
+#[derive(Resource, Default)]
+pub struct Tiles(HashMap<TileCoord, Entity>);
+
+impl Tiles {
+    pub fn get(&self, coord: TileCoord) -> Option<Entity> {
+        self.0.get(&coord).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TileCoord, Entity)> + '_ {
+        self.0.iter().map(|(coord, entity)| (*coord, *entity))
+    }
+}
+
+/// Queues all layers of a tile for loading, unless it already exists.
+#[derive(Event)]
+pub struct LoadTile(pub TileCoord);
+
+/// Loads all layers of a tile again, bypassing the response cache.
+#[derive(Event)]
+pub struct RefreshTile(pub TileCoord);
+
+fn load_tiles(mut events: EventReader<LoadTile>, mut tiles: ResMut<Tiles>, mut commands: Commands) {
+    for LoadTile(coord) in events.read() {
+        tiles
+            .0
+            .entry(*coord)
+            .or_insert_with(|| commands.spawn((Tile(*coord), Queued)).id());
+    }
+}
+
+fn refresh_tiles(
+    mut events: EventReader<RefreshTile>,
+    mut tiles: ResMut<Tiles>,
+    mut commands: Commands,
+) {
+    for RefreshTile(coord) in events.read() {
+        let req = LoadRequest::from_rect(coord.rect());
+
+        if let Err(e) = overpass::cache::invalidate(req.rect()) {
+            warn!("Failed to invalidate cache for tile {:?}: {}", coord, e);
+        }
+
+        match tiles.get(*coord) {
+            Some(entity) => {
+                commands.entity(entity).remove::<Queued>().insert(req);
+            }
+            None => {
+                tiles
+                    .0
+                    .insert(*coord, commands.spawn((Tile(*coord), req)).id());
+            }
+        }
+    }
+}
+
+fn forget_tiles(mut removed: RemovedComponents<Tile>, mut tiles: ResMut<Tiles>) {
+    for entity in removed.read() {
+        tiles.0.retain(|_, e| *e != entity);
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_panorbit_camera::PanOrbitCamera;
+use geo::{HaversineDistance, Point};
+
+use super::{LoadTile, Queued, Tile, TileCoord, Tiles};
+use crate::{
+    loading::{ActiveLoads, LoadRequest},
+    viewport::{MainCamera, OriginCoordinate},
+};
+
+/// Loads are only dispatched while fewer than this many layer loads are running, so the queue
+/// can still be reordered as the camera moves.
+const MAX_ACTIVE_LOADS: usize = 6;
+
+/// Distance around the camera focus that is kept loaded, which grows when zooming out.
+pub(super) fn load_radius(camera: &PanOrbitCamera) -> f64 {
+    let radius = crate::config::get().tiles.load_radius;
+
+    radius.max(camera.radius.unwrap_or(0.) as f64 * 2.)
+}
+
+/// Tiles overlapping the square around `focus` that is kept loaded.
+pub(super) fn wanted_tiles(focus: Point, radius: f64) -> HashSet<TileCoord> {
+    TileCoord::covering(LoadRequest::new(focus, radius).rect()).collect()
+}
+
+/// Queues every tile around the camera focus that isn't loaded yet, and drops queued tiles that
+/// are no longer needed before they start loading.
+pub(super) fn request_tiles(
+    camera: Query<&PanOrbitCamera, With<MainCamera>>,
+    origin: Res<OriginCoordinate>,
+    tiles: Res<Tiles>,
+    queued: Query<(Entity, &Tile), With<Queued>>,
+    mut load_tiles: EventWriter<LoadTile>,
+    mut commands: Commands,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+
+    let wanted = wanted_tiles(origin.to_point(camera.focus), load_radius(camera));
+
+    load_tiles.send_batch(
+        wanted
+            .iter()
+            .filter(|coord| tiles.get(**coord).is_none())
+            .map(|coord| LoadTile(*coord)),
+    );
+
+    for (entity, tile) in &queued {
+        if !wanted.contains(&tile.0) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Starts loading the queued tile nearest to the camera focus, once there's room for it.
+pub(super) fn dispatch_tiles(
+    camera: Query<&PanOrbitCamera, With<MainCamera>>,
+    origin: Res<OriginCoordinate>,
+    queued: Query<(Entity, &Tile), With<Queued>>,
+    starting: Query<(), (With<Tile>, With<LoadRequest>)>,
+    active: Res<ActiveLoads>,
+    mut commands: Commands,
+) {
+    if !starting.is_empty() || active.count() >= MAX_ACTIVE_LOADS {
+        return;
+    }
+
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+
+    let focus = origin.to_point(camera.focus);
+
+    let Some((entity, tile)) = queued.iter().min_by(|(_, a), (_, b)| {
+        let a = focus.haversine_distance(&a.0.center());
+        let b = focus.haversine_distance(&b.0.center());
+        a.total_cmp(&b)
+    }) else {
+        return;
+    };
+
+    commands
+        .entity(entity)
+        .remove::<Queued>()
+        .insert(LoadRequest::from_rect(tile.0.rect()));
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::HaversineDestination;
+
+    use super::*;
+
+    #[test]
+    fn wanted_tiles_cover_the_loaded_square() {
+        let focus = Point::new(13.405, 52.52);
+        let radius = 1500.;
+        let wanted = wanted_tiles(focus, radius);
+
+        assert!(wanted.contains(&TileCoord::from_point(focus)));
+
+        // the corners of the square, which are further away than the radius
+        for bearing in [45., 135., 225., 315.] {
+            let corner = focus.haversine_destination(bearing, radius * 1.4);
+            let coord = TileCoord::from_point(corner);
+
+            assert!(wanted.contains(&coord), "missing tile at bearing {bearing}");
+            assert!(focus.haversine_distance(&coord.center()) > radius);
+        }
+
+        for bearing in [0., 90., 180., 270.] {
+            let outside = focus.haversine_destination(bearing, radius * 3.);
+            assert!(!wanted.contains(&TileCoord::from_point(outside)));
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_egui::{
+    egui::{Layout, TopBottomPanel},
+    EguiContexts,
+};
+use bevy_mod_picking::{focus::HoverMap, prelude::Pickable};
+use geo::Point;
+
+use crate::viewport::{MainCamera, OriginCoordinate};
+
+/// Geographic position of the spot under the mouse, kept while the mouse is over the UI so it can
+/// still be copied from the status bar.
+#[derive(Resource, Default)]
+pub struct Cursor {
+    point: Option<Point>,
+    elevation: f32,
+}
+
+pub fn show_cursor(
+    mut cursor: ResMut<Cursor>,
+    hovers: Res<HoverMap>,
+    pickable: Query<(), With<Pickable>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    mouse: Res<Input<MouseButton>>,
+    origin: Res<OriginCoordinate>,
+    mut egui_contexts: EguiContexts,
+) {
+    let ctx = egui_contexts.ctx_mut();
+
+    if !ctx.is_pointer_over_area() {
+        if let Some(position) = pointer_position(&hovers, &pickable, &window, &camera) {
+            cursor.point = Some(origin.to_point(position));
+            cursor.elevation = position.y;
+        }
+
+        if mouse.just_pressed(MouseButton::Middle) {
+            if let Some(point) = cursor.point {
+                ctx.output_mut(|o| o.copied_text = decimal(point));
+            }
+        }
+    }
+
+    TopBottomPanel::bottom("status").show(ctx, |ui| {
+        ui.with_layout(Layout::right_to_left(Default::default()), |ui| {
+            let Some(point) = cursor.point else {
+                return;
+            };
+
+            for (label, text) in
+                [("URL", osm_url(point)), ("DMS", dms(point)), ("Decimal", decimal(point))]
+            {
+                if ui.small_button(label).on_hover_text("Copy").clicked() {
+                    ui.output_mut(|o| o.copied_text = text);
+                }
+            }
+
+            ui.separator();
+            ui.label(format!("{:.1} m", cursor.elevation));
+            ui.separator();
+            ui.label(format!("{} ({})", decimal(point), dms(point)));
+        });
+    });
+}
+
+/// Where the pointer ray first hits a pickable mesh, or otherwise the ground plane. The sky box and
+/// the ground mesh aren't pickable, so they're skipped in favour of the exact plane at zero height.
+fn pointer_position(
+    hovers: &HoverMap,
+    pickable: &Query<(), With<Pickable>>,
+    window: &Query<&Window, With<PrimaryWindow>>,
+    camera: &Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+) -> Option<Vec3> {
+    let hit = hovers
+        .values()
+        .flat_map(|hits| hits.iter())
+        .filter(|(entity, hit)| hit.position.is_some() && pickable.contains(**entity))
+        .map(|(_, hit)| hit)
+        .min_by(|a, b| a.depth.total_cmp(&b.depth));
+
+    if let Some(position) = hit.and_then(|hit| hit.position) {
+        return Some(position);
+    }
+
+    let cursor = window.get_single().ok()?.cursor_position()?;
+    let (camera, transform) = camera.get_single().ok()?;
+    let ray = camera.viewport_to_world(transform, cursor)?;
+    let distance = ray.intersect_plane(Vec3::ZERO, Vec3::Y)?;
+
+    Some(ray.get_point(distance))
+}
+
+fn decimal(point: Point) -> String {
+    format!("{:.6}, {:.6}", point.y(), point.x())
+}
+
+fn dms(point: Point) -> String {
+    let part = |value: f64, positive: char, negative: char| {
+        let hemisphere = if value < 0. { negative } else { positive };
+        let seconds = (value.abs() * 3600. * 10.).round() / 10.;
+        let degrees = (seconds / 3600.).floor();
+        let minutes = ((seconds - degrees * 3600.) / 60.).floor();
+        let seconds = seconds - degrees * 3600. - minutes * 60.;
+
+        format!("{degrees}°{minutes:02}′{seconds:04.1}″{hemisphere}")
+    };
+
+    format!("{} {}", part(point.y(), 'N', 'S'), part(point.x(), 'E', 'W'))
+}
+
+fn osm_url(point: Point) -> String {
+    format!(
+        "https://www.openstreetmap.org/?mlat={lat:.6}&mlon={lon:.6}#map=19/{lat:.6}/{lon:.6}",
+        lat = point.y(),
+        lon = point.x(),
+    )
+}
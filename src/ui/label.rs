@@ -8,16 +8,19 @@ pub struct Label {
 }
 
 pub fn update_labels(
-    mut labels: Query<(&Label, &mut Style)>,
+    mut labels: Query<(Entity, &Label, &mut Style)>,
     targets: Query<&GlobalTransform>,
     camera_query: Query<(&GlobalTransform, &Camera), With<MainCamera>>,
+    mut commands: Commands,
 ) {
     let Ok((camera_transform, camera)) = camera_query.get_single() else {
         return;
     };
 
-    for (label, mut label_transform) in &mut labels {
+    for (entity, label, mut label_transform) in &mut labels {
         let Ok(target_transform) = targets.get(label.follow) else {
+            // the feature was unloaded
+            commands.entity(entity).despawn_recursive();
             continue;
         };
 
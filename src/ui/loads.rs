@@ -0,0 +1,116 @@
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{Align2, CollapsingHeader, Color32, Ui, Window},
+    EguiContexts,
+};
+use geo::Rect;
+
+use crate::{
+    buildings::Building,
+    loading::{LoadRequest, LoadState, LoadStatus, LoadType},
+    poi::PointOfInterest,
+    roads::Road,
+    tiles::{RefreshTile, Tiles},
+};
+
+pub fn show_loads(
+    buildings: Query<(Entity, &LoadState<Building>)>,
+    roads: Query<(Entity, &LoadState<Road>)>,
+    pois: Query<(Entity, &LoadState<PointOfInterest>)>,
+    tiles: Res<Tiles>,
+    mut refresh: EventWriter<RefreshTile>,
+    mut egui_contexts: EguiContexts,
+    mut commands: Commands,
+) {
+    let ctx = egui_contexts.ctx_mut();
+
+    Window::new("Loads")
+        .anchor(Align2::RIGHT_TOP, [-10., 10.])
+        .resizable(false)
+        .show(ctx, |ui| {
+            let button = ui
+                .button("Refresh all")
+                .on_hover_text("Load every tile again, bypassing the cache");
+            if button.clicked() {
+                for (coord, _) in tiles.iter() {
+                    refresh.send(RefreshTile(coord));
+                }
+            }
+
+            show_layer(ui, "Buildings", &buildings, &mut commands);
+            show_layer(ui, "Roads", &roads, &mut commands);
+            show_layer(ui, "Points of interest", &pois, &mut commands);
+        });
+}
+
+/// Summarises the finished loads of a layer and lists the ones still loading or failed.
+fn show_layer<T: LoadType>(
+    ui: &mut Ui,
+    name: &str,
+    query: &Query<(Entity, &LoadState<T>)>,
+    commands: &mut Commands,
+) {
+    let mut loaded = 0;
+    let mut elements = 0;
+    let mut pending = vec![];
+
+    for (entity, state) in query {
+        match state.status {
+            LoadStatus::Loaded { count } => {
+                loaded += 1;
+                elements += count;
+            }
+            _ => pending.push((entity, state)),
+        }
+    }
+
+    pending.sort_by_key(|(_, state)| state.started);
+
+    let failed = pending
+        .iter()
+        .filter(|(_, state)| matches!(state.status, LoadStatus::Failed { .. }))
+        .count();
+
+    let title = format!("{name}: {} loading, {failed} failed", pending.len() - failed);
+
+    CollapsingHeader::new(title).id_source(name).show(ui, |ui| {
+        ui.label(format!("{loaded} areas loaded, {elements} elements"));
+
+        for (entity, state) in pending {
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if let LoadStatus::Loading = state.status {
+                    ui.spinner();
+                }
+
+                ui.label(format_rect(state.rect));
+                ui.label(format!("{:.1}s", state.elapsed().as_secs_f32()));
+
+                if let LoadStatus::Failed { .. } = state.status {
+                    if ui.button("Retry").clicked() {
+                        commands
+                            .entity(entity)
+                            .insert(LoadRequest::from_rect(state.rect).only::<T>());
+                    }
+                }
+            });
+
+            if let LoadStatus::Failed { errors } = &state.status {
+                for (i, error) in errors.iter().enumerate() {
+                    let text = if i == 0 {
+                        error.clone()
+                    } else {
+                        format!("Caused by: {error}")
+                    };
+                    ui.colored_label(Color32::LIGHT_RED, text);
+                }
+            }
+        }
+    });
+}
+
+/// South-west and north-east corners, in the same order as an Overpass bbox.
+fn format_rect(rect: Rect) -> String {
+    format!("{:.4}, {:.4} – {:.4}, {:.4}", rect.min().y, rect.min().x, rect.max().y, rect.max().x)
+}
@@ -1,4 +1,7 @@
+pub mod cursor;
 pub mod label;
+pub mod loads;
+pub mod search;
 pub mod tooltip;
 
 use bevy::{prelude::*, ui::UiSystem};
@@ -13,7 +16,17 @@ pub struct UiPlugin;
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, setup)
-            .add_systems(Update, tooltip::show_tooltip)
+            .init_resource::<search::Search>()
+            .init_resource::<cursor::Cursor>()
+            .add_systems(
+                Update,
+                (
+                    tooltip::show_tooltip,
+                    loads::show_loads,
+                    search::show_search,
+                    cursor::show_cursor,
+                ),
+            )
             .add_systems(PreUpdate, label::update_labels.before(UiSystem::Layout));
     }
 }
@@ -0,0 +1,93 @@
+use bevy::{
+    prelude::*,
+    tasks::{block_on, AsyncComputeTaskPool, Task},
+};
+use bevy_egui::{
+    egui::{Align2, Color32, Key, TextEdit, Window},
+    EguiContexts,
+};
+use futures::future::poll_immediate;
+
+use crate::{
+    geocoder::{self, Place},
+    viewport::FlyTo,
+};
+
+#[derive(Resource, Default)]
+pub struct Search {
+    query: String,
+    task: Option<Task<anyhow::Result<Vec<Place>>>>,
+    results: Vec<Place>,
+    error: Option<String>,
+}
+
+pub fn show_search(
+    mut search: ResMut<Search>,
+    mut fly_to: EventWriter<FlyTo>,
+    mut egui_contexts: EguiContexts,
+) {
+    if let Some(task) = &mut search.task {
+        if let Some(result) = block_on(poll_immediate(task)) {
+            search.task = None;
+
+            match result {
+                Ok(results) => {
+                    search.error = results.is_empty().then(|| "No results".to_string());
+                    search.results = results;
+                }
+                Err(e) => {
+                    search.error = Some(format!("{:#}", e));
+                    search.results.clear();
+                }
+            }
+        }
+    }
+
+    let ctx = egui_contexts.ctx_mut();
+
+    Window::new("Search")
+        .anchor(Align2::LEFT_TOP, [10., 10.])
+        .resizable(false)
+        .show(ctx, |ui| {
+            let mut submit = false;
+
+            ui.horizontal(|ui| {
+                let input = ui.add(TextEdit::singleline(&mut search.query).hint_text("Place"));
+                submit |= input.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+                submit |= ui.button("Search").clicked();
+
+                if search.task.is_some() {
+                    ui.spinner();
+                }
+            });
+
+            if submit && !search.query.trim().is_empty() {
+                let query = search.query.trim().to_string();
+                search.task = Some(
+                    AsyncComputeTaskPool::get()
+                        .spawn(async move { geocoder::search(&query).await }),
+                );
+            }
+
+            if let Some(error) = &search.error {
+                ui.colored_label(Color32::LIGHT_RED, error);
+            }
+
+            let mut selected = false;
+
+            for place in &search.results {
+                let response = ui
+                    .selectable_label(false, &place.display_name)
+                    .on_hover_text(format!("{} {}", place.category, place.kind));
+
+                if response.clicked() {
+                    fly_to.send(FlyTo(place.point()));
+                    selected = true;
+                }
+            }
+
+            if selected {
+                search.results.clear();
+            }
+        });
+}
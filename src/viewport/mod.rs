@@ -8,9 +8,9 @@ use bevy::{
 };
 use bevy_atmosphere::plugin::{AtmosphereCamera, AtmospherePlugin};
 use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
-use geo::{HaversineBearing, HaversineDistance, Point};
+use geo::{Coord, Point};
 
-use crate::{color, common::WorldPosition, loading::LoadRequest, COLORS};
+use crate::{color, common::WorldPosition, projection::Projection, COLORS};
 
 #[derive(Default)]
 pub struct ViewportPlugin;
@@ -18,7 +18,7 @@ pub struct ViewportPlugin;
 impl Plugin for ViewportPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins((AtmospherePlugin, PanOrbitCameraPlugin))
-            .insert_resource(OriginCoordinate(Point::new(139.77137176176117, 35.69967697464613)))
+            .insert_resource(OriginCoordinate::new(crate::config::get().start.origin))
             .register_diagnostic(
                 Diagnostic::new(
                     view_distance::VIEW_DISTANCE_DIAGNOSTIC,
@@ -27,17 +27,69 @@ impl Plugin for ViewportPlugin {
                 )
                 .with_suffix(view_distance::VIEW_DISTANCE_DIAGNOSTIC_SUFFIX),
             )
+            .add_event::<FlyTo>()
             .add_systems(Startup, setup)
-            .add_systems(Update, (give_position, view_distance::update_visibility));
+            .add_systems(
+                Update,
+                ((fly_to, rebase_origin, give_position).chain(), view_distance::update_visibility),
+            );
     }
 }
 
+/// Distance from the origin in metres beyond which the camera focus becomes the new origin, to keep
+/// everything near the camera within the precision of f32 transforms.
+const REBASE_DISTANCE: f32 = 2000.;
+
+/// Where the world is placed on the earth. Coordinates are projected around a centre that only
+/// changes when flying somewhere else, and the world origin sits at an offset from it that follows
+/// the camera, so positions near the camera stay small enough for f32 transforms.
 #[derive(Resource)]
-pub struct OriginCoordinate(pub Point);
+pub struct OriginCoordinate {
+    center: Point,
+    /// Metres east and north of the centre.
+    offset: Coord,
+}
+
+impl OriginCoordinate {
+    pub fn new(center: Point) -> Self {
+        Self {
+            center,
+            offset: Coord { x: 0., y: 0. },
+        }
+    }
+
+    pub fn projection(&self) -> Projection {
+        Projection::new(self.center)
+    }
+
+    /// Coordinate at the world origin.
+    pub fn point(&self) -> Point {
+        self.projection().unproject(self.offset)
+    }
+
+    /// Position of a coordinate in the world, on the ground plane.
+    pub fn to_world(&self, point: Point) -> Vec3 {
+        let coord = self.projection().project(point) - self.offset;
+
+        Vec3::new(coord.x as f32, 0., -coord.y as f32)
+    }
+
+    /// Coordinate of a position in the world, the inverse of [`Self::to_world`].
+    pub fn to_point(&self, position: Vec3) -> Point {
+        self.projection().unproject(Coord {
+            x: self.offset.x + position.x as f64,
+            y: self.offset.y - position.z as f64,
+        })
+    }
+}
 
 #[derive(Component)]
 pub struct MainCamera;
 
+/// Moves the camera to a coordinate, which becomes the new origin.
+#[derive(Event)]
+pub struct FlyTo(pub Point);
+
 fn setup(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
@@ -126,8 +178,6 @@ fn setup(
         transform: Transform::from_translation(Vec3::new(0., -1., 0.)),
         ..default()
     });
-
-    commands.spawn(LoadRequest::new(Point::new(139.77137176176117, 35.69967697464613), 1000.));
 }
 
 fn give_position(
@@ -136,13 +186,7 @@ fn give_position(
     mut commands: Commands,
 ) {
     for (entity, WorldPosition(point)) in query.iter() {
-        let distance = origin.0.haversine_distance(point);
-        let bearing = origin.0.haversine_bearing(*point);
-
-        let ang = bearing.to_radians();
-
-        let translation =
-            Vec3::new((distance * ang.sin()) as f32, 0., (distance * -ang.cos()) as f32);
+        let translation = origin.to_world(*point);
 
         commands.entity(entity).insert(SpatialBundle {
             transform: Transform::from_translation(translation),
@@ -150,3 +194,72 @@ fn give_position(
         });
     }
 }
+
+/// Moves the origin to the camera focus once it gets too far away, and shifts every positioned
+/// entity and the camera along with it so nothing moves on screen. The projection stays the same,
+/// so translating entities puts them exactly where projecting their coordinate again would. The
+/// sky and ground are left in place, which recentres them on the camera.
+fn rebase_origin(
+    mut camera: Query<(&mut PanOrbitCamera, &mut Transform), With<MainCamera>>,
+    mut positioned: Query<
+        &mut Transform,
+        (With<WorldPosition>, Without<Parent>, Without<MainCamera>),
+    >,
+    mut origin: ResMut<OriginCoordinate>,
+) {
+    let Ok((mut camera, mut camera_transform)) = camera.get_single_mut() else {
+        return;
+    };
+
+    let offset = Vec3::new(camera.focus.x, 0., camera.focus.z);
+    if offset.length() < REBASE_DISTANCE {
+        return;
+    }
+
+    origin.offset.x += offset.x as f64;
+    origin.offset.y -= offset.z as f64;
+
+    camera.focus -= offset;
+    camera.target_focus -= offset;
+    camera_transform.translation -= offset;
+
+    for mut transform in &mut positioned {
+        transform.translation -= offset;
+    }
+
+    debug!("Moved origin to {:?}", origin.point());
+}
+
+/// Jumps to another place, which also becomes the centre of the projection. Unlike
+/// [`rebase_origin`], everything already loaded is positioned again from its coordinate. Its
+/// meshes were built with the old projection, but it'll be out of range and unloaded soon anyway,
+/// and the new surroundings start streaming in.
+fn fly_to(
+    mut events: EventReader<FlyTo>,
+    mut camera: Query<(&mut PanOrbitCamera, &mut Transform), With<MainCamera>>,
+    mut positioned: Query<(&WorldPosition, &mut Transform), (Without<Parent>, Without<MainCamera>)>,
+    mut origin: ResMut<OriginCoordinate>,
+) {
+    let Some(FlyTo(point)) = events.read().last() else {
+        return;
+    };
+
+    let Ok((mut camera, mut camera_transform)) = camera.get_single_mut() else {
+        return;
+    };
+
+    *origin = OriginCoordinate::new(*point);
+
+    let offset = Vec3::new(camera.focus.x, 0., camera.focus.z);
+    camera.focus -= offset;
+    camera.target_focus -= offset;
+    camera_transform.translation -= offset;
+
+    for (WorldPosition(point), mut transform) in &mut positioned {
+        let translation = origin.to_world(*point);
+        transform.translation.x = translation.x;
+        transform.translation.z = translation.z;
+    }
+
+    info!("Flying to {:?}", point);
+}